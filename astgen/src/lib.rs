@@ -11,7 +11,8 @@ use heck::SnakeCase;
 /// ```text
 /// generate_ast!(
 ///     $AST_NAME,
-///     [$(NODE_NAME => { $($FIELD_NAME: $FIELD_TYPE),+)+ }])
+///     $({ $($SHARED_NAME: $SHARED_TYPE),+ },)?
+///     [$(NODE_NAME => $(Rc)? { $($FIELD_NAME: $FIELD_TYPE),+)+ }])
 /// )
 /// ```
 ///
@@ -19,6 +20,7 @@ use heck::SnakeCase;
 /// ```text
 /// generate_ast!(
 ///     Expr,
+///     { span: Span },
 ///     [
 ///         Number => { value: isize };
 ///         Binary => { left: Box<Expr>, op: char, right: Box<Expr> };
@@ -27,6 +29,7 @@ use heck::SnakeCase;
 /// ```
 struct Ast {
     name: Ident,
+    shared: Punctuated<Field, Token![,]>,
     nodes: Punctuated<AstNode, Token![;]>,
 }
 
@@ -34,15 +37,25 @@ impl Parse for Ast {
     fn parse(input: ParseStream) -> Result<Self> {
         let name: Ident = input.parse()?;
         input.parse::<Token![,]>()?;
+        let shared = if input.peek(syn::token::Brace) {
+            let shared_input;
+            syn::braced!(shared_input in input);
+            let shared = shared_input.parse_terminated(Field::parse)?;
+            input.parse::<Token![,]>()?;
+            shared
+        } else { Punctuated::new() };
         let nodes_input;
         syn::bracketed!(nodes_input in input);
         let nodes: Punctuated<AstNode, Token![;]> = nodes_input.parse_terminated(AstNode::parse)?;
-        Ok(Ast { name, nodes })
+        Ok(Ast { name, shared, nodes })
     }
 }
 
 struct AstNode {
     name: Ident,
+    // Whether the enum holds the node behind an `Rc`, so that it can be
+    // shared with whatever outlives the tree rather than copied.
+    rc: bool,
     fields: Punctuated<Field, Token![,]>,
 }
 
@@ -50,10 +63,17 @@ impl Parse for AstNode {
     fn parse(input: ParseStream) -> Result<Self> {
         let name: Ident = input.parse()?;
         input.parse::<Token![=>]>()?;
+        let rc = if input.peek(Ident) {
+            let rc: Ident = input.parse()?;
+            if rc != "Rc" {
+                return Err(syn::Error::new(rc.span(), "Expected `Rc` or a list of fields."))
+            }
+            true
+        } else { false };
         let fields_input;
         syn::braced!(fields_input in input);
         let fields = fields_input.parse_terminated(Field::parse)?;
-        Ok(AstNode { name, fields })
+        Ok(AstNode { name, rc, fields })
     }
 }
 
@@ -75,6 +95,16 @@ impl Parse for Field {
 /// enum, with a case for each node type, new_{node} functions for each
 /// node, as well as a visitor trait with a visit function per node.
 ///
+/// Every node also carries an `id` which is expected to be unique within a
+/// program, so that later passes can attach information to a particular
+/// node without hashing its contents. Any shared fields (such as a source
+/// span) are added to every node after the id, and get an accessor on the
+/// enum. Neither ids nor shared fields are considered when comparing or
+/// hashing nodes.
+///
+/// A node written as `Name => Rc { ... }` is held by the enum as an
+/// `Rc<Name>`, and its visit function takes the `Rc`.
+///
 /// Example: the following invocation:
 /// ```text
 /// generate_ast!(
@@ -93,25 +123,46 @@ impl Parse for Field {
 ///     Literal(Literal),
 /// }
 ///
-/// #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// #[derive(Clone, Debug)]
 /// pub struct Binary {
+///     pub(crate) id: usize,
 ///     pub(crate) left: Box<Expr>,
 ///     pub(crate) op: Token,
 ///     pub(crate) right: Box<Expr>,
 /// }
 ///
-/// #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-/// pub struct Literal {
-///     pub(crate) value: usize,
+/// impl PartialEq for Binary {
+///     fn eq(&self, other: &Self) -> bool {
+///         true && self.left == other.left && self.op == other.op && self.right == other.right
+///     }
+/// }
+///
+/// impl Eq for Binary {}
+///
+/// impl Hash for Binary {
+///     fn hash<H: Hasher>(&self, state: &mut H) {
+///         self.left.hash(state);
+///         self.op.hash(state);
+///         self.right.hash(state);
+///     }
 /// }
 ///
+/// // ...and likewise for Literal.
+///
 /// impl Expr {
-///     pub(crate) fn new_binary(left: Box<Expr>, op: Token, right: Box<Expr>) -> Self {
-///         Self::Binary(Binary { left, op, right })
+///     pub(crate) fn new_binary(id: usize, left: Box<Expr>, op: Token, right: Box<Expr>) -> Self {
+///         Self::Binary(Binary { id, left, op, right })
+///     }
+///
+///     pub(crate) fn new_literal(id: usize, value: usize) -> Self {
+///         Self::Literal(Literal { id, value })
 ///     }
 ///
-///     pub(crate) fn new_literal(value: usize) -> Self {
-///         Self::Literal(Literal { value })
+///     pub(crate) fn id(&self) -> usize {
+///         match self {
+///             Expr::Binary(a) => a.id,
+///             Expr::Literal(a) => a.id,
+///         }
 ///     }
 /// }
 ///
@@ -134,9 +185,13 @@ impl Parse for Field {
 pub fn generate_ast(input: TokenStream) -> TokenStream {
     let Ast {
         name,
+        shared,
         nodes,
     } = syn::parse_macro_input!(input);
 
+    let shared_names: Vec<_> = shared.iter().map(|f| &f.name).collect();
+    let shared_types: Vec<_> = shared.iter().map(|f| &f.ty).collect();
+
     let lowercase_name = name.to_string().to_lowercase();
     let (node_names, visit_names): (Vec<_>, Vec<_>) = nodes.iter().map(|n| {
         let visit_name = quote::format_ident!("visit_{}_{}", n.name.to_string().to_snake_case(), lowercase_name);
@@ -144,10 +199,15 @@ pub fn generate_ast(input: TokenStream) -> TokenStream {
         (name, visit_name)
     }).unzip();
 
+    let node_types: Vec<_> = nodes.iter().map(|n| {
+        let name = &n.name;
+        if n.rc { quote! { std::rc::Rc<#name> } } else { quote! { #name } }
+    }).collect();
+
     let ast_enum = quote! {
         #[derive(Clone, Debug, Eq, Hash, PartialEq)]
         pub enum #name {
-            #(#node_names(#node_names)),*
+            #(#node_names(#node_types)),*
         }
     };
 
@@ -155,11 +215,29 @@ pub fn generate_ast(input: TokenStream) -> TokenStream {
         let node_name = &n.name;
         let field_names = n.fields.iter().map(|f| &f.name);
         let field_types = n.fields.iter().map(|f| &f.ty);
+        let eq_names = field_names.clone();
+        let hash_names = field_names.clone();
         quote! {
-            #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+            #[derive(Clone, Debug)]
             pub struct #node_name {
+                pub(crate) id: usize,
+                #(pub(crate) #shared_names: #shared_types,)*
                 #(pub(crate) #field_names: #field_types),*
             }
+
+            impl PartialEq for #node_name {
+                fn eq(&self, other: &Self) -> bool {
+                    true #(&& self.#eq_names == other.#eq_names)*
+                }
+            }
+
+            impl Eq for #node_name {}
+
+            impl std::hash::Hash for #node_name {
+                fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {
+                    #(std::hash::Hash::hash(&self.#hash_names, _state);)*
+                }
+            }
         }
     });
 
@@ -170,11 +248,36 @@ pub fn generate_ast(input: TokenStream) -> TokenStream {
         let arg_names = n.fields.iter().map(|f| &f.name);
         let field_names = arg_names.clone();
         let field_types = n.fields.iter().map(|f| &f.ty);
+        let node = quote! {
+            #struct_name {
+                id,
+                #(#shared_names,)*
+                #(#field_names),*
+            }
+        };
+        let node = if n.rc { quote! { std::rc::Rc::new(#node) } } else { node };
         quote! {
-            pub(crate) fn #snake_node_name(#(#arg_names: #field_types),*) -> Self {
-                Self::#enum_case(#struct_name {
-                    #(#field_names),*
-                })
+            // Constructors take every field of their node, however many
+            // there are.
+            #[allow(clippy::too_many_arguments)]
+            pub(crate) fn #snake_node_name(
+                id: usize,
+                #(#shared_names: #shared_types,)*
+                #(#arg_names: #field_types),*
+            ) -> Self {
+                Self::#enum_case(#node)
+            }
+        }
+    });
+
+    let shared_accessors = shared.iter().map(|f| {
+        let field_name = &f.name;
+        let field_type = &f.ty;
+        quote! {
+            pub(crate) fn #field_name(&self) -> &#field_type {
+                match self {
+                    #(#name::#node_names(a) => &a.#field_name,)*
+                }
             }
         }
     });
@@ -182,12 +285,20 @@ pub fn generate_ast(input: TokenStream) -> TokenStream {
     let enum_impl = quote! {
         impl #name {
             #(#constructor_fns)*
+
+            #(#shared_accessors)*
+
+            pub(crate) fn id(&self) -> usize {
+                match self {
+                    #(#name::#node_names(a) => a.id,)*
+                }
+            }
         }
     };
 
     let visitor = quote! {
         pub(crate) trait Visitor<T> {
-            #(fn #visit_names(&mut self, e: &#node_names) -> T;)*
+            #(fn #visit_names(&mut self, e: &#node_types) -> T;)*
         }
 
         impl #name {
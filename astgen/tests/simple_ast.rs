@@ -8,7 +8,7 @@ fn can_generate_ast() {
         TestAst,
         [ S => { s: String } ]
     );
-    let _node = TestAst::S(S { s: "".into() });
+    let _node = TestAst::S(S { id: 0, s: "".into() });
 }
 
 #[test]
@@ -17,7 +17,7 @@ fn can_change_ast_name() {
         OtherAst,
         [ S => { s: String } ]
     );
-    let _node = OtherAst::S(S { s: "".into() });
+    let _node = OtherAst::S(S { id: 0, s: "".into() });
 }
 
 #[test]
@@ -26,13 +26,13 @@ fn uses_field_names_for_struct_fields() {
         Example,
         [ S => { s: String, i: isize } ]
     );
-    let _node = Example::S(S { s: "".into(), i: 0 });
+    let _node = Example::S(S { id: 0, s: "".into(), i: 0 });
 }
 
 #[test]
 fn can_clone_nodes() {
     generate_ast!(A, [N => {a: usize}]);
-    let n = N { a: 0 };
+    let n = N { id: 0, a: 0 };
     let _clone = n.clone();
 }
 
@@ -45,8 +45,8 @@ fn uses_node_names_for_enum_variants() {
             B => { b: String };
         ]
     );
-    let _a_node = Example::A(A { a: 0 });
-    let _b_node = Example::B(B { b: "".into() });
+    let _a_node = Example::A(A { id: 0, a: 0 });
+    let _b_node = Example::B(B { id: 1, b: "".into() });
 }
 
 #[test]
@@ -58,8 +58,8 @@ fn generates_new_fns() {
             B => { b: usize };
         ]
     );
-    let _a_node = Test::new_a(1);
-    let _b_node = Test::new_b(8);
+    let _a_node = Test::new_a(0, 1);
+    let _b_node = Test::new_b(1, 8);
 }
 
 #[test]
@@ -73,8 +73,8 @@ fn generates_visitor_trait() {
     );
     struct V;
     impl Visitor<()> for V {
-        fn visit_a_node_some(&mut self, _a: &ANode) { () }
-        fn visit_other_node_some(&mut self, _o: &OtherNode) { () }
+        fn visit_a_node_some(&mut self, _a: &ANode) {}
+        fn visit_other_node_some(&mut self, _o: &OtherNode) {}
     }
 }
 
@@ -94,11 +94,46 @@ fn accept_fn_routes_calls_to_correct_visitor_fn() {
         fn visit_not_me_visitme(&mut self, _t: &NotMe) {}
         fn visit_test_visitme(&mut self, _t: &Test) {
             self.called = true;
-            ()
         }
     }
     let mut visitor = V { called: false };
-    let node = VisitMe::Test(Test { a: 0 });
+    let node = VisitMe::Test(Test { id: 0, a: 0 });
     node.accept(&mut visitor);
     assert!(visitor.called);
+}
+
+#[test]
+fn nodes_expose_their_id() {
+    generate_ast!(
+        Ids,
+        [
+            A => { a: isize };
+            B => { b: String };
+        ]
+    );
+    assert_eq!(3, Ids::new_a(3, 0).id());
+    assert_eq!(7, Ids::new_b(7, "".into()).id());
+}
+
+#[test]
+fn ids_are_ignored_when_comparing_nodes() {
+    generate_ast!(Compare, [N => { a: usize }]);
+    assert_eq!(Compare::new_n(0, 1), Compare::new_n(1, 1));
+    assert_ne!(Compare::new_n(0, 1), Compare::new_n(0, 2));
+}
+
+#[test]
+fn shared_fields_are_added_to_every_node() {
+    generate_ast!(
+        Shared,
+        { line: usize },
+        [
+            A => { a: isize };
+            B => { b: String };
+        ]
+    );
+    let a = Shared::new_a(0, 4, 1);
+    let _b = B { id: 1, line: 2, b: "".into() };
+    assert_eq!(&4, a.line());
+    assert_eq!(a, Shared::new_a(1, 5, 1));
 }
\ No newline at end of file
@@ -1,17 +1,24 @@
 use rlox_treewalk::{
-    interpreter::Interpreter,
-    parser::Parser,
-    resolver::Resolver,
-    scanner::Scanner,
+    lox::{self, RunError},
+    Capabilities,
+    Lox,
 };
 use std::{
-    cell::RefCell,
     env,
     io::{self, Write},
-    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+// Scripts run on a thread with a much bigger stack than the main one, so
+// they can recurse far deeper than the interpreter allows by default.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+const MAX_CALL_DEPTH: usize = 50_000;
+
 fn main() -> io::Result<()> {
+    lox::with_stack_size(STACK_SIZE, run_main)
+}
+
+fn run_main() -> io::Result<()> {
     let mut stdout = io::stdout();
     let mut stderr = io::stderr();
 
@@ -30,14 +37,15 @@ fn main() -> io::Result<()> {
 
 fn run_file(path: &str, out: &mut io::Stdout, err_out: &mut io::Stderr) -> io::Result<()> {
     let contents = std::fs::read_to_string(path)?;
-    Lox::new(out, err_out).run(contents.as_str())
+    let mut lox = new_lox(out);
+    run(&mut lox, contents.as_str(), err_out)
  }
 
 fn run_prompt(out: &mut io::Stdout, err_out: &mut io::Stderr) -> io::Result<()> {
     let mut buffer = String::new();
     let stdin = io::stdin();
 
-    let mut lox = Lox::new(io::stdout(), err_out);
+    let mut lox = new_lox(io::stdout());
 
     loop {
         write!(out, "> ")?;
@@ -48,64 +56,40 @@ fn run_prompt(out: &mut io::Stdout, err_out: &mut io::Stderr) -> io::Result<()>
         let num_bytes = stdin.read_line(&mut buffer)?;
         if num_bytes == 0 { break };
 
-        lox.run(buffer.as_str())?;
+        run(&mut lox, buffer.as_str(), err_out)?;
     }
 
     Ok(())
 }
 
-struct Lox<Out, ErrOut> {
-    interpreter: Rc<RefCell<Interpreter<Out>>>,
-    resolver: Resolver<Out>,
-    err_out: ErrOut,
+/// An interpreter which can do whatever the user running it can, and
+/// doesn't do the same thing every time.
+fn new_lox<W: Write>(out: W) -> Lox<W> {
+    let mut lox = Lox::with_capabilities(out, Capabilities::all());
+    lox.set_max_call_depth(MAX_CALL_DEPTH);
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default();
+    lox.seed_random(seed);
+    lox
 }
 
-impl <Out: Write, ErrOut: Write> Lox<Out, ErrOut> {
-    fn new(out: Out, err_out: ErrOut) -> Self {
-        let interpreter = Rc::new(RefCell::new(Interpreter::new(out)));
-        let resolver = Resolver::new(&interpreter);
-        Self {
-            interpreter,
-            resolver,
-            err_out,
-        }
+fn run<W: Write>(lox: &mut Lox<W>, source: &str, err_out: &mut io::Stderr) -> io::Result<()> {
+    let result = lox.run_source(source);
+    for warning in lox.warnings() {
+        writeln!(err_out, "{}", warning.with_source(source))?;
     }
 
-    fn run(&mut self, source: &str) -> io::Result<()> {
-        let scanner = Scanner::new(source);
-        let (tokens, errors): (Vec<_>, Vec<_>) = scanner.into_iter().partition(Result::is_ok);
-
-        let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
-        if !errors.is_empty() {
-            for e in errors.iter() {
-                writeln!(self.err_out, "{}", e)?;
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            writeln!(err_out, "{}", e.with_source(source))?;
+            match e {
+                RunError::Scan(_) | RunError::Parse(_) => std::process::exit(65),
+                RunError::Runtime(_) => std::process::exit(70),
+                RunError::Resolve(_) => Ok(()),
             }
-            std::process::exit(65);
-        }
-
-        let tokens: Vec<_> = tokens.into_iter().map(Result::unwrap).collect();
-        let mut parser = Parser::new(tokens.into_iter());
-        let (statements, errors): (Vec<_>, Vec<_>) = parser.parse().into_iter().partition(Result::is_ok);
-
-        let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
-        if !errors.is_empty() {
-            for e in errors.iter() {
-                writeln!(self.err_out, "{}", e)?;
-            }
-            std::process::exit(65);
-        }
-        let statements: Vec<_> = statements.into_iter().map(Result::unwrap).collect();
-
-        if let Err(e) = self.resolver.resolve_stmts(&statements) {
-            return writeln!(self.err_out, "{}", e)
-        }
-
-        match self.interpreter.borrow_mut().interpret(&statements) {
-            Err(e) => {
-                writeln!(self.err_out, "{}", e)?;
-                std::process::exit(70)
-            },
-            Ok(()) => Ok(())
-        }
+        },
     }
 }
\ No newline at end of file
@@ -1,19 +1,54 @@
 use crate::{
-    error::Error,
+    error::{self, Error},
     environment::Environment,
     interpreter::{self, Interpreter, Thrown},
+    methods::BuiltinMethod,
     value::{ClassPointer, Function, NativeFn, Value},
 };
-use std::io::Write;
+use std::{
+    fmt::{self, Display},
+    io::Write,
+};
+
+/// The body of a host function. It is handed the interpreter which called
+/// it, along with the already-evaluated arguments.
+pub type NativeBody<W> = dyn Fn(&mut Interpreter<W>, &[Value]) -> error::Result<Value>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == *n,
+            Arity::AtLeast(n) => count >= *n,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exactly(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
 
 pub(crate) trait Callable<W: Write> {
-    fn arity(&self) -> usize;
+    /// The name shown for calls to this in a stack trace.
+    fn name(&self) -> String;
+    fn arity(&self) -> Arity;
     fn call(&self, interpreter: &mut Interpreter<W>, args: Vec<Value>) -> interpreter::Result<Value>;
 }
 
 impl Value {
     pub(crate) fn callable<W: Write>(&self) -> Option<&dyn Callable<W>> {
         match self {
+            Value::BuiltinMethod(ref m) => Some(m),
             Value::Class(ref c) => Some(c),
             Value::Function(ref f) => Some(f),
             Value::NativeFn(ref n) => Some(n),
@@ -22,13 +57,35 @@ impl Value {
     }
 }
 
-impl <W: Write> Callable<W> for NativeFn<&'static dyn Fn() -> Value> {
-    fn arity(&self) -> usize {
-        0
+impl <W: Write> Callable<W> for NativeFn {
+    fn name(&self) -> String {
+        self.name.clone()
     }
 
-    fn call(&self, _interpreter: &mut Interpreter<W>, _args: Vec<Value>) -> interpreter::Result<Value> {
-        Ok((self.body)())
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter<W>, args: Vec<Value>) -> interpreter::Result<Value> {
+        let body = interpreter.native_body(self)?;
+        let result = body(interpreter, &args)?;
+        // Natives can hand back strings of any size, read from a file say.
+        interpreter.heap.grow(result.size()).map_err(Error::from)?;
+        Ok(result)
+    }
+}
+
+impl <W: Write> Callable<W> for BuiltinMethod {
+    fn name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter<W>, args: Vec<Value>) -> interpreter::Result<Value> {
+        self.call_with(&args, &mut interpreter.heap).map_err(Thrown::from)
     }
 }
 
@@ -41,8 +98,12 @@ impl Function {
 }
 
 impl <W: Write> Callable<W> for Function {
-    fn arity(&self) -> usize {
-        self.declaration.params.len()
+    fn name(&self) -> String {
+        Function::name(self).to_string()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::Exactly(self.declaration.params.len())
     }
 
     fn call(&self, interpreter: &mut Interpreter<W>, args: Vec<Value>) -> interpreter::Result<Value> {
@@ -54,6 +115,7 @@ impl <W: Write> Callable<W> for Function {
             environment.define(&param.lexeme, arg)
         }
 
+        let environment = interpreter.heap.alloc(environment).map_err(Error::from)?;
         match interpreter.execute_block(&self.declaration.body, environment) {
             Ok(_) if self.is_init => self.this_value(),
             Ok(()) => Ok(Value::Nil),
@@ -65,18 +127,27 @@ impl <W: Write> Callable<W> for Function {
 }
 
 impl <W: Write> Callable<W> for ClassPointer {
-    fn arity(&self) -> usize {
+    fn name(&self) -> String {
+        ClassPointer::name(self)
+    }
+
+    fn arity(&self) -> Arity {
         match self.get_field("init") {
-            Some(Value::Function(f)) => f.declaration.params.len(),
-            _ => 0
+            Some(Value::Function(f)) => Arity::Exactly(f.declaration.params.len()),
+            // Without an initializer of their own, errors take a message.
+            _ if self.is_error() => Arity::Exactly(1),
+            _ => Arity::Exactly(0)
         }
 
     }
 
     fn call(&self, interpreter: &mut Interpreter<W>, args: Vec<Value>) -> interpreter::Result<Value> {
-        let instance = self.instantiate();
+        let instance = self.instantiate(&mut interpreter.heap).map_err(Error::from)?;
         if let Some(Value::Function(init)) = self.get_field("init") {
-            init.binding(instance.clone()).call(interpreter, args)?;
+            init.binding(instance.clone(), &mut interpreter.heap).map_err(Error::from)?.call(interpreter, args)?;
+        } else if self.is_error() {
+            instance.set("message", &args[0]);
+            instance.set("line", &Value::Nil);
         }
         Ok(Value::Instance(instance))
     }
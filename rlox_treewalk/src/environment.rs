@@ -1,12 +1,14 @@
 use std::{
     collections::{HashMap},
+    mem,
     rc::Rc,
     cell::RefCell
 };
 use crate::{
     error::{Error, Result},
+    gc::{self, Trace},
     token::Token,
-    value::Value,
+    value::{self, Value},
 };
 
 #[derive(Clone, Debug, PartialEq)]
@@ -30,7 +32,7 @@ impl Environment {
             .unwrap_or_else(|| {
                 self.enclosing.as_ref()
                     .map(|e| e.borrow().get(name))
-                    .unwrap_or_else(|| Err(undefined_var_error(&name)))
+                    .unwrap_or_else(|| Err(undefined_var_error(name)))
             })
     }
 
@@ -43,13 +45,13 @@ impl Environment {
     pub(crate) fn get_at(&self, distance: usize, name: &Token) -> Result<Value> {
         self.with_ancestor_at(distance, |e| {
             e.values.get(&name.lexeme).cloned()
-        }).ok_or_else(|| undefined_var_error(&name))
+        }).ok_or_else(|| undefined_var_error(name))
     }
 
     pub(crate) fn assign_at(&mut self, distance: usize, name: &Token, value: &Value) -> Result<()> {
         self.mutate_ancestor_at(distance, |e| {
             e.values.get_mut(&name.lexeme).map(|v| *v = value.clone())
-        }).ok_or_else(|| undefined_var_error(&name))
+        }).ok_or_else(|| undefined_var_error(name))
     }
 
     fn mutate_ancestor_at<T, F: Fn(&mut Environment) -> T>(&mut self, distance: usize, f: F) -> T {
@@ -80,11 +82,14 @@ impl Environment {
 
     pub(crate) fn assign(&mut self, name: &Token, value: &Value) -> Result<()> {
         self.values.get_mut(&name.lexeme)
-            .map(|v| Ok(*v = value.clone()))
+            .map(|v| {
+                *v = value.clone();
+                Ok(())
+            })
             .unwrap_or_else(|| {
                 self.enclosing.as_ref()
                     .map(|e| e.borrow_mut().assign(name, value))
-                    .unwrap_or_else(|| Err(undefined_var_error(&name)))
+                    .unwrap_or_else(|| Err(undefined_var_error(name)))
             })
     }
 
@@ -93,6 +98,24 @@ impl Environment {
     }
 }
 
+impl Trace for Environment {
+    fn trace(&self, edges: &mut Vec<usize>) {
+        edges.extend(self.enclosing.iter().map(gc::address));
+        for value in self.values.values() {
+            value.trace(edges);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.enclosing = None;
+        self.values.clear();
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Environment>() + value::fields_size(&self.values)
+    }
+}
+
 fn undefined_var_error(name: &Token) -> Error {
     Error::runtime(
         name.clone(),
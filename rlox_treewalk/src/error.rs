@@ -1,75 +1,224 @@
 use std::result;
 use std::fmt::{self, Display};
 
-use crate::token::{Token, TokenKind};
+use crate::{
+    limits::Limit,
+    token::{Span, Token, TokenKind},
+};
 
 pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum ErrorKind {
-    Lexical { line: usize },
+    Lexical { line: usize, span: Span },
     Syntactic { token: Token },
     Static { token: Token },
     Runtime { token: Token },
+    Warning { token: Token },
+    Native,
+    /// Execution was stopped by one of the interpreter's `Limits`.
+    Limit(Limit),
     Unexpected,
     Io(std::io::Error),
 }
 
 #[derive(Debug)]
 pub struct Error {
-    kind: ErrorKind,
+    kind: Box<ErrorKind>,
     message: String,
+    // The part of the source to highlight, when it's wider than the token.
+    span: Option<Span>,
+    // The calls in progress when a runtime error was raised, innermost
+    // first. Empty for every other kind of error.
+    trace: Vec<TraceFrame>,
+}
+
+/// A call which was in progress when a runtime error was raised.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceFrame {
+    /// The line being executed in this call.
+    pub line: usize,
+    /// The name of the function being called, or `None` for the top level
+    /// of the script.
+    pub function: Option<String>,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {}()", self.line, function),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
 }
 
 impl Error {
-    pub fn lexical<S: Into<String>>(line: usize, message: S) -> Error {
-        let kind = ErrorKind::Lexical { line };
-        Error { kind, message: message.into() }
+    pub fn lexical<S: Into<String>>(line: usize, span: Span, message: S) -> Error {
+        Error::new(ErrorKind::Lexical { line, span }, message)
     }
 
     pub fn syntactic<S: Into<String>>(token: Token, message: S) -> Error {
-        let kind = ErrorKind::Syntactic { token };
-        Error { kind, message: message.into() }
+        Error::new(ErrorKind::Syntactic { token }, message)
     }
 
     pub fn runtime<S: Into<String>>(token: Token, message: S) -> Error {
-        let kind = ErrorKind::Runtime { token };
-        Error { kind, message: message.into() }
+        Error::new(ErrorKind::Runtime { token }, message)
+    }
+
+    /// An error raised by a host function. The interpreter doesn't know
+    /// where in the script a native was called from until it returns, so
+    /// these are turned into runtime errors at the call site.
+    pub fn native<S: Into<String>>(message: S) -> Error {
+        Error::new(ErrorKind::Native, message)
+    }
+
+    pub(crate) fn limit(limit: Limit) -> Error {
+        Error::new(ErrorKind::Limit(limit), limit.to_string())
     }
 
     pub fn static_analyzer<S: Into<String>>(token: Token, message: S) -> Error {
-        let kind = ErrorKind::Static { token };
-        Error { kind, message: message.into() }
+        Error::new(ErrorKind::Static { token }, message)
+    }
+
+    /// A diagnostic for code which is legal but probably a mistake.
+    pub fn warning<S: Into<String>>(token: Token, message: S) -> Error {
+        Error::new(ErrorKind::Warning { token }, message)
     }
 
     pub fn unexpected() -> Error {
-        let kind = ErrorKind::Unexpected;
-        Error { kind, message: "Unexpected end of input.".into() }
+        Error::new(ErrorKind::Unexpected, "Unexpected end of input.")
+    }
+
+    fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Error {
+        Error { kind: Box::new(kind), message: message.into(), span: None, trace: Vec::new() }
+    }
+
+    /// Highlights `span` rather than just the error's token when the error
+    /// is shown alongside its source.
+    pub(crate) fn spanning(mut self, span: Span) -> Error {
+        self.span = Some(span);
+        self
+    }
+
+    pub(crate) fn with_trace(mut self, trace: Vec<TraceFrame>) -> Error {
+        self.trace = trace;
+        self
+    }
+
+    /// A copy of a runtime error, for when a script throws one it caught
+    /// again. Only runtime errors can be caught, so nothing else is copied.
+    pub(crate) fn rethrown(&self) -> Option<Error> {
+        match self.kind() {
+            ErrorKind::Runtime { token } => Some(Error {
+                kind: Box::new(ErrorKind::Runtime { token: token.clone() }),
+                message: self.message.clone(),
+                span: self.span,
+                trace: self.trace.clone(),
+            }),
+            _ => None,
+        }
     }
 
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
 
-    pub fn is_runtime_error(&self) -> bool {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> usize {
+        use ErrorKind::*;
         match self.kind() {
-            ErrorKind::Runtime { token: _ } => true,
-            _ => false,
+            Unexpected | Native | Limit(_) | Io(_) => 0,
+            Lexical { line, .. } => *line,
+            Syntactic { token } | Runtime { token } | Static { token } | Warning { token } => token.line,
         }
     }
 
-    fn loc(&self) -> String {
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.trace
+    }
+
+    pub fn is_runtime_error(&self) -> bool {
+        matches!(self.kind(), ErrorKind::Runtime { .. })
+    }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(self.kind(), ErrorKind::Warning { .. })
+    }
+
+    pub(crate) fn token(&self) -> Option<&Token> {
         use ErrorKind::*;
         match self.kind() {
-            Syntactic { token } | Runtime { token } | Static { token } => {
-                if token.kind == TokenKind::EndOfFile {
-                    " at end".to_string()
-                } else {
-                    format!(" at {}", token.lexeme)
+            Syntactic { token } | Runtime { token } | Static { token } | Warning { token } => Some(token),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn at_call_site(self, paren: &Token, call: Span) -> Error {
+        match *self.kind {
+            ErrorKind::Native => Error::runtime(paren.clone(), self.message).spanning(call),
+            _ => self,
+        }
+    }
+
+    /// Wraps the error so that it displays with a snippet of the source
+    /// it came from, underlining the offending code.
+    pub fn with_source<'a>(&'a self, source: &'a str) -> WithSource<'a, Error> {
+        WithSource { inner: self, source }
+    }
+
+    fn span_in(&self, source: &str) -> Option<Span> {
+        let span = match self.kind() {
+            ErrorKind::Lexical { span, .. } => self.span.unwrap_or(*span),
+            _ => {
+                // A REPL session can report errors in code from earlier
+                // inputs, in which case we don't have the right source.
+                let token = self.token()?;
+                if source.get(token.span.start..token.span.end) != Some(token.lexeme.as_str()) {
+                    return None
                 }
+                self.span.unwrap_or(token.span)
             },
-            _ =>  "".to_string(),
+        };
+        // Nothing stops an error being shown with a source other than its
+        // own, so the span has to be checked before it's used to slice.
+        source.get(span.start..span.end).map(|_| span)
+    }
+
+    fn write_header(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // A script can be stopped anywhere, so there's no line to report.
+        if let ErrorKind::Limit(_) = self.kind() {
+            return write!(f, "Error: {}", self.message)
+        }
+        let label = if self.is_warning() { "Warning" } else { "Error" };
+        write!(f, "[line {}] {}{}: {}", self.line(), label, self.loc(), self.message)
+    }
+
+    /// Writes the trace one frame per line, collapsing runs of identical
+    /// frames so that deep recursion doesn't bury everything else.
+    fn write_trace(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            write!(f, "\n{}", frame)?;
+            let mut repeats = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                write!(f, "\n[Previous line repeated {} more times]", repeats)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn loc(&self) -> String {
+        match self.token() {
+            Some(token) if token.kind == TokenKind::EndOfFile => " at end".to_string(),
+            Some(token) => format!(" at {}", token.lexeme),
+            None => "".to_string(),
         }
     }
 }
@@ -78,14 +227,8 @@ impl std::error::Error for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use ErrorKind::*;
-        let line = match self.kind() {
-            Unexpected => 0,
-            Io(_e) => 0,
-            Lexical { line } => *line,
-            Syntactic { token } | Runtime { token } | Static { token }  => token.line,
-        };
-        write!(f, "[line {}] Error{}: {}", line, self.loc(), self.message)
+        self.write_header(f)?;
+        self.write_trace(f)
     }
 }
 
@@ -98,6 +241,42 @@ impl From<Error> for std::io::Error {
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Error {
-        Error { kind: ErrorKind::Io(e), message: "IO error".into() }
+        Error::new(ErrorKind::Io(e), "IO error")
+    }
+}
+
+pub struct WithSource<'a, T> {
+    pub(crate) inner: &'a T,
+    pub(crate) source: &'a str,
+}
+
+impl Display for WithSource<'_, Error> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.write_header(f)?;
+        if let Some(span) = self.inner.span_in(self.source) {
+            write_snippet(f, self.source, span)?;
+        }
+        self.inner.write_trace(f)
     }
+}
+
+/// Writes the line containing the start of `span`, underlining the part of
+/// it covered by the span, in roughly the same format as rustc.
+fn write_snippet(f: &mut fmt::Formatter<'_>, source: &str, span: Span) -> fmt::Result {
+    let before = &source[..span.start];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+    let line_number = before.matches('\n').count() + 1;
+    let prefix = &source[line_start..span.start];
+    let column = prefix.chars().count() + 1;
+
+    // Keep any tabs so the underline lines up with the code above it.
+    let padding: String = prefix.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let width = source[span.start..span.end.min(line_end)].chars().count().max(1);
+
+    let gutter = " ".repeat(line_number.to_string().len());
+    write!(f, "\n{}--> line {}, column {}", gutter, line_number, column)?;
+    write!(f, "\n{} |", gutter)?;
+    write!(f, "\n{} | {}", line_number, source[line_start..line_end].trim_end_matches('\r'))?;
+    write!(f, "\n{} | {}{}", gutter, padding, "^".repeat(width))
 }
\ No newline at end of file
@@ -1,18 +1,29 @@
-use crate::token::Token;
+use std::rc::Rc;
+
+use crate::stmt;
+use crate::token::{Span, Token};
 use crate::value;
 use astgen::generate_ast;
 
 generate_ast!(
     Expr,
+    { span: Span },
     [
         Assign   => { name: Token, value: Box<Expr> };
         Binary   => { left: Box<Expr>, op: Token, right: Box<Expr> };
         Call     => { callee: Box<Expr>, paren: Token, arguments: Vec<Expr> };
         Get      => { object: Box<Expr>, name: Token };
         Grouping => { expression: Box<Expr> };
+        Index    => { object: Box<Expr>, bracket: Token, index: Box<Expr> };
+        // An anonymous function, which is named after its `fun` keyword.
+        Lambda   => { function: Rc<stmt::Function> };
+        List     => { bracket: Token, elements: Vec<Expr> };
         Literal  => { value: value::Literal };
         Logical  => { left: Box<Expr>, op: Token, right: Box<Expr> };
+        Map      => { brace: Token, entries: Vec<(Expr, Expr)> };
         Set      => { object: Box<Expr>, name: Token, value: Box<Expr> };
+        SetIndex => { object: Box<Expr>, bracket: Token, index: Box<Expr>, value: Box<Expr> };
+        Stringify => { expression: Box<Expr> };
         Super    => { keyword: Token, method: Token };
         This     => { keyword: Token };
         Unary    => { op: Token, right: Box<Expr> };
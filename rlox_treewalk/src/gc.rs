@@ -0,0 +1,280 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{error::Error, token::Token};
+
+/// Lox objects which can refer to other objects, and so can take part in
+/// reference cycles.
+pub(crate) trait Trace {
+    /// Pushes the address of every object directly referenced by `self`.
+    fn trace(&self, edges: &mut Vec<usize>);
+
+    /// Drops every reference held by `self`. This is only ever called on
+    /// garbage, to break the cycles keeping it alive.
+    fn clear(&mut self);
+
+    /// Roughly how many bytes `self` takes up, including the contents of
+    /// any strings it holds but not the objects it refers to.
+    fn size(&self) -> usize;
+}
+
+pub(crate) fn address<T: ?Sized>(object: &Rc<RefCell<T>>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+/// What the collector has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// The number of collections which have run.
+    pub collections: usize,
+    /// The number of objects reclaimed by all of those collections.
+    pub freed: usize,
+    /// The number of objects which survived the last collection.
+    pub live: usize,
+}
+
+/// How much memory scripts are using, in bytes. These are estimates: they
+/// count what the interpreter's values hold rather than what the allocator
+/// hands out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    /// The memory in use right now. Objects which have become unreachable
+    /// are only taken off this once a collection notices them.
+    pub current: usize,
+    /// The most memory that has been in use at once.
+    pub peak: usize,
+}
+
+/// The heap couldn't make room for an allocation without going over its
+/// memory limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct OutOfMemory;
+
+impl OutOfMemory {
+    /// The error for running out of memory at `token`.
+    pub(crate) fn at(self, token: &Token) -> Error {
+        Error::runtime(token.clone(), "Out of memory.")
+    }
+}
+
+impl From<OutOfMemory> for Error {
+    /// Without a token to blame, this is reported at whichever call site
+    /// reached the native or method which ran out.
+    fn from(_: OutOfMemory) -> Self {
+        Error::native("Out of memory.")
+    }
+}
+
+const INITIAL_THRESHOLD: usize = 1024;
+
+/// Every value is reference counted, which frees most objects as soon as
+/// they're unreachable. The heap keeps track of the objects which could
+/// form cycles so that a collection can find and break the ones which
+/// reference counting never will.
+pub(crate) struct Heap {
+    objects: Vec<Weak<RefCell<dyn Trace>>>,
+    threshold: usize,
+    stats: GcStats,
+    memory: MemoryUsage,
+    memory_limit: Option<usize>,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            threshold: INITIAL_THRESHOLD,
+            stats: GcStats::default(),
+            memory: MemoryUsage::default(),
+            memory_limit: None,
+        }
+    }
+
+    pub(crate) fn alloc<T: Trace + 'static>(&mut self, object: T) -> Result<Rc<RefCell<T>>, OutOfMemory> {
+        self.grow(object.size())?;
+        let object = Rc::new(RefCell::new(object));
+        let traced: Rc<RefCell<dyn Trace>> = object.clone();
+        self.objects.push(Rc::downgrade(&traced));
+        Ok(object)
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.objects.len() >= self.threshold
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Counts `bytes` more memory as being in use, for when something grows
+    /// after it's been allocated. If that would go over the limit, garbage is
+    /// collected first, and only if it still would is the memory refused.
+    pub(crate) fn grow(&mut self, bytes: usize) -> Result<(), OutOfMemory> {
+        self.reserve(bytes)?;
+        self.count(bytes);
+        Ok(())
+    }
+
+    /// Counts `bytes` more memory as being in use whatever the limit, for
+    /// memory the host asked for rather than a script.
+    pub(crate) fn count(&mut self, bytes: usize) {
+        self.memory.current += bytes;
+        self.memory.peak = self.memory.peak.max(self.memory.current);
+    }
+
+    /// Makes sure there's room for `bytes` more memory without counting it,
+    /// so that something which is expensive to build can be refused before
+    /// it's built. Whatever's built still has to be counted once it exists.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Result<(), OutOfMemory> {
+        if self.would_exceed_limit(bytes) {
+            // Whatever the interpreter is using is held from outside of the
+            // heap, so it's kept alive without being named as a root.
+            self.collect(&[]);
+            if self.would_exceed_limit(bytes) {
+                return Err(OutOfMemory)
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` without the memory limit, for the little a script needs in
+    /// order to recover from running out of memory.
+    pub(crate) fn unlimited<T>(&mut self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        let limit = self.memory_limit.take();
+        let result = f(self);
+        self.memory_limit = limit;
+        result
+    }
+
+    /// Whether using `bytes` more memory would go over the limit.
+    fn would_exceed_limit(&self, bytes: usize) -> bool {
+        self.memory_limit.is_some_and(|limit| self.memory.current.saturating_add(bytes) > limit)
+    }
+
+    pub(crate) fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        self.memory
+    }
+
+    /// Frees every object which isn't reachable from `roots`, returning how
+    /// many there were.
+    ///
+    /// Anything held outside of the heap - by a Rust local part way through
+    /// evaluating an expression, say - is also treated as a root. Those are
+    /// found by counting the references each object gets from other objects
+    /// in the heap: an object with more references than that must be held
+    /// by something else.
+    ///
+    /// The memory in use is recounted from the objects which survive, which
+    /// leaves out anything only held outside of the heap.
+    pub(crate) fn collect(&mut self, roots: &[usize]) -> usize {
+        let objects: Vec<_> = self.objects.iter().filter_map(Weak::upgrade).collect();
+        let index: HashMap<usize, usize> = objects.iter()
+            .enumerate()
+            .map(|(i, object)| (address(object), i))
+            .collect();
+
+        // Ignore the reference each of `objects` holds itself.
+        let mut external: Vec<_> = objects.iter().map(|o| Rc::strong_count(o) - 1).collect();
+        let mut edges = vec![Vec::new(); objects.len()];
+        let mut sizes = vec![0; objects.len()];
+        let mut marked = vec![false; objects.len()];
+        let mut pending = Vec::new();
+
+        for (i, object) in objects.iter().enumerate() {
+            match object.try_borrow() {
+                Ok(object) => {
+                    let mut addresses = Vec::new();
+                    object.trace(&mut addresses);
+                    sizes[i] = object.size();
+                    edges[i] = addresses.into_iter().filter_map(|a| index.get(&a).copied()).collect();
+                    for &target in edges[i].iter() {
+                        external[target] -= 1;
+                    }
+                },
+                // Something is using this object right now, so it can't
+                // possibly be garbage.
+                Err(_) => pending.push(i),
+            }
+        }
+
+        pending.extend(roots.iter().filter_map(|root| index.get(root).copied()));
+        pending.extend((0..objects.len()).filter(|&i| external[i] > 0));
+
+        while let Some(i) = pending.pop() {
+            if marked[i] {
+                continue
+            }
+            marked[i] = true;
+            pending.extend(edges[i].iter().copied().filter(|&target| !marked[target]));
+        }
+
+        let mut freed = 0;
+        for (object, _) in objects.iter().zip(marked.iter()).filter(|(_, &marked)| !marked) {
+            if let Ok(mut object) = object.try_borrow_mut() {
+                object.clear();
+                freed += 1;
+            }
+        }
+
+        let live = marked.iter().filter(|&&marked| marked).count();
+        self.memory.current = sizes.iter().zip(marked.iter()).filter(|(_, &marked)| marked).map(|(size, _)| size).sum();
+        // Objects can grow without being counted, so the recount can come
+        // to more than has ever been counted before.
+        self.memory.peak = self.memory.peak.max(self.memory.current);
+        drop(objects);
+        self.objects.retain(|object| object.strong_count() > 0);
+
+        self.threshold = INITIAL_THRESHOLD.max(live * 2);
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live = live;
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Value;
+
+    use super::*;
+
+    #[test]
+    fn the_limit_is_checked_when_memory_is_counted() {
+        let mut heap = Heap::new();
+        let _list = heap.alloc(vec![Value::Nil; 4]).unwrap();
+        let size = heap.memory_usage().current;
+        heap.set_memory_limit(Some(size));
+        assert_eq!(Err(OutOfMemory), heap.grow(1));
+        assert!(heap.alloc(vec![Value::Nil]).is_err());
+        assert_eq!(size, heap.memory_usage().current);
+    }
+
+    #[test]
+    fn garbage_is_collected_before_memory_is_refused() {
+        let mut heap = Heap::new();
+        let list = heap.alloc(vec![Value::Nil; 4]).unwrap();
+        let size = heap.memory_usage().current;
+        heap.set_memory_limit(Some(size));
+        drop(list);
+        assert!(heap.alloc(vec![Value::Nil; 4]).is_ok());
+        assert_eq!(1, heap.stats().collections);
+    }
+
+    #[test]
+    fn recounting_memory_keeps_the_peak_up_to_date() {
+        let mut heap = Heap::new();
+        let list = heap.alloc(Vec::new()).unwrap();
+        list.borrow_mut().extend([Value::Nil, Value::Nil]);
+        heap.collect(&[]);
+        let usage = heap.memory_usage();
+        assert_eq!(usage.peak, usage.current);
+        assert!(usage.current > Vec::<Value>::new().size());
+    }
+}
\ No newline at end of file
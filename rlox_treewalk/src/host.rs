@@ -0,0 +1,168 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    value::Value,
+};
+
+/// Where `clock()` gets the time from, in milliseconds since the Unix
+/// epoch. Any closure returning a number will do.
+pub trait TimeSource {
+    fn now(&mut self) -> f64;
+}
+
+impl <F: FnMut() -> f64> TimeSource for F {
+    fn now(&mut self) -> f64 {
+        self()
+    }
+}
+
+/// The real time, according to the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&mut self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time since epoch should never be negative")
+            .as_millis() as f64
+    }
+}
+
+/// Something a native read from the host, as it was handed to the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Nil,
+    Number(f64),
+    String(String),
+    /// The native failed with this message.
+    Error(String),
+}
+
+impl Input {
+    fn from_result(result: &Result<Value>) -> Self {
+        match result {
+            Ok(Value::Nil) => Input::Nil,
+            Ok(Value::Number(n)) => Input::Number(*n),
+            Ok(Value::String(s)) => Input::String(s.clone()),
+            Ok(_) => unreachable!("Natives only read nil, numbers and strings from the host."),
+            Err(e) => Input::Error(e.message().to_string()),
+        }
+    }
+
+    fn into_result(self) -> Result<Value> {
+        match self {
+            Input::Nil => Ok(Value::Nil),
+            Input::Number(n) => Ok(Value::Number(n)),
+            Input::String(s) => Ok(Value::String(s)),
+            Input::Error(message) => Err(Error::native(message)),
+        }
+    }
+}
+
+/// Everything a run read from the host, in order, along with the name of
+/// the native which read it. Replaying it makes the run happen again
+/// exactly, whatever the host would say this time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub inputs: Vec<(String, Input)>,
+}
+
+enum Mode {
+    Live,
+    Recording(Recording),
+    Replaying(VecDeque<(String, Input)>),
+}
+
+/// A SplitMix64 generator. It's tiny and plenty random enough for scripts,
+/// but most importantly it does the same thing for a seed on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// The interpreter's view of everything outside the script which could make
+/// two runs of it behave differently.
+pub(crate) struct Host {
+    time: Box<dyn TimeSource>,
+    rng: Rng,
+    mode: Mode,
+}
+
+impl Host {
+    pub(crate) fn new() -> Self {
+        Host { time: Box::new(SystemClock), rng: Rng(0), mode: Mode::Live }
+    }
+
+    pub(crate) fn set_time_source<T: TimeSource + 'static>(&mut self, time: T) {
+        self.time = Box::new(time);
+    }
+
+    pub(crate) fn seed_random(&mut self, seed: u64) {
+        self.rng = Rng(seed);
+    }
+
+    pub(crate) fn now(&mut self) -> f64 {
+        self.time.now()
+    }
+
+    /// A number between 0 (inclusive) and 1 (exclusive).
+    pub(crate) fn random(&mut self) -> f64 {
+        // The top 53 bits fill an f64's mantissa exactly.
+        (self.rng.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn record(&mut self) {
+        self.mode = Mode::Recording(Recording::default());
+    }
+
+    pub(crate) fn take_recording(&mut self) -> Option<Recording> {
+        match std::mem::replace(&mut self.mode, Mode::Live) {
+            Mode::Recording(recording) => Some(recording),
+            mode => {
+                self.mode = mode;
+                None
+            },
+        }
+    }
+
+    pub(crate) fn replay(&mut self, recording: Recording) {
+        self.mode = Mode::Replaying(recording.inputs.into());
+    }
+
+    /// Runs `native`, which reaches the host, unless a recording is being
+    /// replayed, in which case the next recorded input is used instead. The
+    /// native doesn't run at all then, so a replay never writes files again.
+    pub(crate) fn read(
+        &mut self,
+        name: &str,
+        native: impl FnOnce(&mut Host) -> Result<Value>,
+    ) -> Result<Value> {
+        if let Mode::Replaying(inputs) = &mut self.mode {
+            return match inputs.pop_front() {
+                Some((recorded, input)) if recorded == name => input.into_result(),
+                Some((recorded, _)) => Err(Error::native(format!(
+                    "Replay expected a call to {} rather than {}.", recorded, name
+                ))),
+                None => Err(Error::native(format!("Replay has no input left for {}.", name))),
+            }
+        }
+
+        let result = native(self);
+        if let Mode::Recording(recording) = &mut self.mode {
+            recording.inputs.push((name.to_string(), Input::from_result(&result)));
+        }
+        result
+    }
+}
\ No newline at end of file
@@ -1,25 +1,62 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Write,
     rc::Rc,
-    time::{SystemTime, UNIX_EPOCH}
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
 };
 
 use crate::{
+    callable::{Arity, Callable, NativeBody},
     environment::Environment,
-    error::{Error, self},
+    error::{Error, ErrorKind, TraceFrame, self},
     expr::{self, Expr},
+    gc::{self, GcStats, Heap, MemoryUsage},
+    host::{Host, Recording, TimeSource},
+    limits::{Limit, Limits},
+    methods::{self, BuiltinMethod},
+    natives::{self, Capabilities, Capability},
     stmt::{self, Stmt},
-    token::{TokenKind, Token},
-    value::Value,
+    token::{Span, TokenKind, Token},
+    value::{ClassMembers, ClassPointer, ListPointer, Map, MapPointer, NativeFn, Value},
 };
 
 pub(crate) type Result<T> = std::result::Result<T, Thrown>;
 
+// Natives are looked up in the interpreter which defined them, so every
+// interpreter needs an id of its own to tell them apart.
+static NEXT_INTERPRETER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Every Lox call can take over ten kilobytes of the host's stack in a debug
+/// build, so the default limit is low enough to fit in the 2MiB stacks that
+/// spawned threads get. Scripts which need to recurse deeper should be run on
+/// a larger stack with a higher limit.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 128;
+
+// Reading the clock is slow next to evaluating a node, so the deadline is
+// only checked this often.
+const DEADLINE_INTERVAL: u64 = 256;
+
 pub(crate) enum Thrown {
     Error(Error),
     Return(Value),
+    Break,
+    Continue,
+    Exception(Box<Exception>),
+}
+
+/// A value thrown by a `throw` statement.
+pub(crate) struct Exception {
+    value: Value,
+    keyword: Token,
+    trace: Vec<TraceFrame>,
+}
+
+/// A call to a function which hasn't returned yet.
+struct CallFrame {
+    function: String,
+    call_site: Token,
 }
 
 impl From<error::Error> for Thrown {
@@ -35,52 +72,362 @@ impl From<std::io::Error> for Thrown {
 }
 
 pub struct Interpreter<W> {
+    id: usize,
     globals: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    natives: Vec<Rc<NativeBody<W>>>,
+    // The natives which weren't defined because the capability they need
+    // wasn't granted.
+    denied: HashMap<&'static str, Capability>,
+    // Globals which scripts can read but can't assign to, until a script
+    // declares a global of the same name itself.
+    constants: HashSet<&'static str>,
+    locals: HashMap<usize, usize>,
     environment: Rc<RefCell<Environment>>,
+    frames: Vec<CallFrame>,
+    max_call_depth: usize,
+    limits: Limits,
+    steps: u64,
+    // The limit which stopped the script, if one has. It stays stopped
+    // until the limits are set again.
+    stopped: Option<Limit>,
+    // The class of the objects runtime errors are turned into when they're
+    // caught.
+    error_class: ClassPointer,
+    pub(crate) heap: Heap,
+    pub(crate) host: Host,
     writer: W,
 }
 
 impl <W: Write> Interpreter<W> {
-    pub fn new(writer: W) -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-
-        globals.borrow_mut().define("clock", Value::new_native_fn(&|| {
-            let time = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time since epoch should never be negative")
-                .as_millis();
-            Value::Number(time as f64)
-        }));
-
+    /// Creates an interpreter which prints to `writer` and can only reach
+    /// the parts of the host that `capabilities` grants.
+    pub fn new(writer: W, capabilities: Capabilities) -> Self {
+        let mut heap = Heap::new();
+        let globals = heap.alloc(Environment::new()).expect("New heaps have no memory limit.");
         let locals = HashMap::new();
-        let environment = Rc::new(RefCell::new(Environment::from(&globals)));
-        Interpreter {
+        let environment = Rc::clone(&globals);
+        let error_class = ClassPointer::new_error_class(&mut heap)
+            .expect("New heaps have no memory limit.");
+        let mut interpreter = Interpreter {
+            id: NEXT_INTERPRETER_ID.fetch_add(1, Ordering::Relaxed),
             globals,
+            natives: Vec::new(),
+            denied: HashMap::new(),
+            constants: HashSet::new(),
             locals,
             environment,
+            frames: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            limits: Limits::default(),
+            steps: 0,
+            stopped: None,
+            error_class,
+            heap,
+            host: Host::new(),
             writer,
-        }
+        };
+
+        let error_class = Value::Class(interpreter.error_class.clone());
+        interpreter.define_global("Error".into(), error_class);
+        let denied = natives::define_natives(&mut interpreter, capabilities);
+        interpreter.denied = denied.into_iter().collect();
+        interpreter
+    }
+
+    /// Defines a global function which is implemented by the host. Errors
+    /// created with `Error::native` are reported at the Lox call site.
+    pub fn define_native<S, F>(&mut self, name: S, arity: Arity, body: F)
+    where
+        S: Into<String>,
+        F: Fn(&mut Interpreter<W>, &[Value]) -> error::Result<Value> + 'static,
+    {
+        let name = name.into();
+        let id = self.natives.len();
+        self.natives.push(Rc::new(body));
+        self.define_global(name.clone(), Value::new_native_fn(name, arity, self.id, id));
+    }
+
+    /// Defines a global which scripts can't assign to, though they can
+    /// still declare their own global in its place.
+    pub(crate) fn define_constant(&mut self, name: &'static str, value: Value) {
+        self.constants.insert(name);
+        self.define_global(name.to_string(), value);
+    }
+
+    /// Defines a global for the host. It counts towards the memory in use,
+    /// but it's never refused.
+    fn define_global(&mut self, name: String, value: Value) {
+        self.heap.count(name.len() + value.size());
+        self.globals.borrow_mut().define(name, value);
+    }
+
+    pub(crate) fn native_body(&self, native: &NativeFn) -> error::Result<Rc<NativeBody<W>>> {
+        self.natives.get(native.id)
+            .filter(|_| native.interpreter == self.id)
+            .map(Rc::clone)
+            .ok_or_else(|| Error::native(format!("{} was defined by a different interpreter.", native.name)))
     }
 
     pub fn interpret(&mut self, statements: &[Stmt]) -> error::Result<()> {
         for s in statements.iter() {
             match self.execute(s) {
-                Err(Thrown::Return(_v)) => unreachable!("return should never make it this far up the stack."),
                 Err(Thrown::Error(e)) => return Err(e),
+                Err(Thrown::Exception(e)) => return Err(self.uncaught(*e)),
+                Err(_) => unreachable!("return, break and continue should never make it this far up the stack."),
                 _ => continue
             }
         }
         Ok(())
     }
 
+    pub(crate) fn interpret_expression(&mut self, e: &Expr) -> error::Result<Value> {
+        match self.evaluate(e) {
+            Err(Thrown::Error(e)) => Err(e),
+            Err(Thrown::Exception(e)) => Err(self.uncaught(*e)),
+            Err(_) => unreachable!("return, break and continue should never make it this far up the stack."),
+            Ok(v) => Ok(v),
+        }
+    }
+
+    fn uncaught(&self, exception: Exception) -> Error {
+        // A caught runtime error can be thrown again, in which case its own
+        // message is more useful than the object it was turned into.
+        let description = match &exception.value {
+            Value::Instance(i) if i.is_error() => i.field("message")
+                .map(|message| message.to_string())
+                .unwrap_or_default(),
+            value => value.to_string(),
+        };
+        Error::runtime(exception.keyword, format!("Uncaught exception: {}", description))
+            .with_trace(exception.trace)
+    }
+
+    /// The calls in progress, innermost first, given that the innermost is
+    /// currently executing `token`. This is empty at the top level, where
+    /// the trace would only repeat the line `token` is on.
+    fn trace(&self, token: &Token) -> Vec<TraceFrame> {
+        if self.frames.is_empty() {
+            return Vec::new()
+        }
+        let lines = std::iter::once(token.line)
+            .chain(self.frames.iter().rev().map(|frame| frame.call_site.line));
+        let functions = self.frames.iter()
+            .rev()
+            .map(|frame| Some(frame.function.clone()))
+            .chain(std::iter::once(None));
+        lines.zip(functions)
+            .map(|(line, function)| TraceFrame { line, function })
+            .collect()
+    }
+
+    /// Attaches a backtrace to `thrown` if it's a runtime error which
+    /// doesn't have one yet. This has to happen before the call the error
+    /// was raised in is popped off the stack.
+    fn traced(&self, thrown: Thrown) -> Thrown {
+        match thrown {
+            Thrown::Error(error) if error.is_runtime_error() && error.trace().is_empty() => {
+                let trace = error.token().map(|token| self.trace(token)).unwrap_or_default();
+                Thrown::Error(error.with_trace(trace))
+            },
+            thrown => thrown,
+        }
+    }
+
+    /// The value a `catch` clause binds for `thrown`, or `thrown` itself if
+    /// it isn't something which can be caught.
+    fn caught(&mut self, thrown: Thrown) -> std::result::Result<Value, Thrown> {
+        match thrown {
+            Thrown::Exception(exception) => Ok(exception.value),
+            Thrown::Error(error) if error.is_runtime_error() => {
+                // The error could be that there's no memory left, which the
+                // script can't recover from without somewhere to catch it.
+                let error_class = &self.error_class;
+                let object = self.heap.unlimited(|heap| error_class.instantiate(heap))
+                    .expect("Allocations without a limit always succeed.");
+                object.set("message", &Value::from(error.message()));
+                object.set("line", &Value::Number(error.line() as f64));
+                object.set_origin(error);
+                Ok(Value::Instance(object))
+            },
+            thrown => Err(thrown),
+        }
+    }
+
+    /// Calls `callee` from `call_site`, with a frame on the stack for it
+    /// while it runs. Every call goes through here, whether it's written as
+    /// one or made implicitly by an accessor.
+    fn call(&mut self, callee: &dyn Callable<W>, args: Vec<Value>, call_site: &Token, span: Span) -> Result<Value> {
+        if self.frames.len() >= self.max_call_depth {
+            return Err(Thrown::Error(Error::runtime(call_site.clone(), "Stack overflow.").spanning(span)))
+        }
+        // Calls are where most objects get allocated, so this is a good
+        // place to notice that they've used too much memory.
+        self.allocate(0, call_site)?;
+        self.frames.push(CallFrame { function: callee.name(), call_site: call_site.clone() });
+        let result = callee.call(self, args).map_err(|t| self.traced(t));
+        self.frames.pop();
+        result.map_err(|t| match t {
+            Thrown::Error(err) => Thrown::Error(err.at_call_site(call_site, span)),
+            t => t,
+        })
+    }
+
+    /// Limits how deeply calls can nest before a "Stack overflow." runtime
+    /// error is raised, rather than letting deep recursion overflow the
+    /// host's stack.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    /// Replaces the limits on how much work scripts can do, and restarts
+    /// the count of steps taken. Scripts which were stopped by the old
+    /// limits can run again.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.steps = 0;
+        self.stopped = None;
+    }
+
+    /// The number of statements and expressions evaluated since the limits
+    /// were last set.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Counts a step towards the limits, failing if any of them have been
+    /// exceeded.
+    fn step(&mut self) -> Result<()> {
+        self.steps += 1;
+        let limits = &self.limits;
+        let exceeded = if self.stopped.is_some() {
+            self.stopped
+        } else if limits.max_steps.is_some_and(|max| self.steps > max) {
+            Some(Limit::Steps)
+        } else if limits.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+            Some(Limit::Cancelled)
+        } else if self.steps.is_multiple_of(DEADLINE_INTERVAL) && limits.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(Limit::Deadline)
+        } else {
+            None
+        };
+        self.stopped = exceeded;
+        match exceeded {
+            Some(limit) => Err(Thrown::Error(Error::limit(limit))),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the source `clock()` gets the time from.
+    pub fn set_time_source<T: TimeSource + 'static>(&mut self, time: T) {
+        self.host.set_time_source(time);
+    }
+
+    /// Restarts the numbers `random()` returns from `seed`. Without this
+    /// every interpreter returns the same sequence.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.host.seed_random(seed);
+    }
+
+    /// Starts recording everything natives read from the host, so that it
+    /// can be replayed later.
+    pub fn record(&mut self) {
+        self.host.record();
+    }
+
+    /// Stops recording, returning what's been recorded since `record` was
+    /// called, or `None` if it wasn't.
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.host.take_recording()
+    }
+
+    /// Answers every native which reaches the host with the next input from
+    /// `recording`, rather than asking the host. Natives which change the
+    /// host, like `writeFile`, are skipped. A run which reads something
+    /// different from what was recorded fails.
+    pub fn replay(&mut self, recording: Recording) {
+        self.host.replay(recording);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().maybe_get_at(0, name)
+    }
+
+    pub fn set_global<S: Into<String>>(&mut self, name: S, value: Value) {
+        self.define_global(name.into(), value);
+    }
+
+    /// Reclaims every object which is no longer reachable, returning how
+    /// many there were. This happens automatically as objects are
+    /// allocated, so there's rarely any need to call it directly.
+    pub fn collect_garbage(&mut self) -> usize {
+        let roots = [gc::address(&self.globals), gc::address(&self.environment)];
+        self.heap.collect(&roots)
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Limits the memory scripts can use to roughly `limit` bytes. Going
+    /// over it raises an "Out of memory." runtime error, which scripts can
+    /// catch once they've let go of enough.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.heap.set_memory_limit(limit);
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.heap.memory_usage()
+    }
+
+    /// Accounts for `bytes` more memory being used by the code at `token`.
+    /// Objects count themselves when they're allocated, so this is for
+    /// growth and strings.
+    fn allocate(&mut self, bytes: usize, token: &Token) -> Result<()> {
+        self.heap.grow(bytes).map_err(|e| Thrown::Error(e.at(token)))
+    }
+
+    /// Puts `environment` on the heap, blaming `token` if there's no room.
+    pub(crate) fn alloc_environment(&mut self, environment: Environment, token: &Token) -> Result<Rc<RefCell<Environment>>> {
+        self.heap.alloc(environment).map_err(|e| Thrown::Error(e.at(token)))
+    }
+
+    /// Fails if `name` is a constant, which scripts can't assign to.
+    fn check_writable(&self, name: &Token) -> Result<()> {
+        if self.constants.contains(name.lexeme.as_str()) {
+            return Err(Thrown::Error(Error::runtime(name.clone(), "Can't assign to constant.")))
+        }
+        Ok(())
+    }
+
+    /// Lets a script's own global declaration of `name` replace a constant,
+    /// so that scripts which already use the name keep working.
+    fn take_over_constant(&mut self, name: &Token) {
+        if self.is_global_scope() {
+            self.constants.remove(name.lexeme.as_str());
+        }
+    }
+
+    fn is_global_scope(&self) -> bool {
+        Rc::ptr_eq(&self.environment, &self.globals)
+    }
+
     fn execute(&mut self, s: &Stmt) -> Result<()> {
+        // Only collect between statements, when as little as possible is
+        // held by half-evaluated expressions.
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.step()?;
         s.accept(self)
     }
 
-    pub(crate) fn execute_block(&mut self, statements: &[Stmt], environment: Environment) -> Result<()> {
+    pub(crate) fn execute_block(&mut self, statements: &[Stmt], environment: Rc<RefCell<Environment>>) -> Result<()> {
         let old_env = Rc::clone(&self.environment);
-        self.environment = Rc::new(RefCell::new(environment));
+        self.environment = environment;
         for statement in statements {
             // Reset the environment before returning an error.
             if let Err(error) = self.execute(statement) {
@@ -93,32 +440,60 @@ impl <W: Write> Interpreter<W> {
     }
     
     fn evaluate(&mut self, e: &Expr) -> Result<Value> {
+        self.step()?;
         e.accept(self)
     }
 
-    fn lookup_variable(&mut self, name: &Token, e: &Expr) -> Result<Value> {
-        if let Some(distance) = self.locals.get(&e) {
+    fn lookup_variable(&mut self, name: &Token, id: usize) -> Result<Value> {
+        if let Some(distance) = self.locals.get(&id) {
             self.environment.borrow_mut()
-                .get_at(*distance, &name)
-                .map_err(|e| Thrown::Error(e))
+                .get_at(*distance, name)
+                .map_err(Thrown::Error)
         } else {
-            self.globals.borrow().get(&name).map_err(Thrown::from)
+            self.globals.borrow().get(name).map_err(|error| match self.denied.get(name.lexeme.as_str()) {
+                Some(capability) => Thrown::Error(Error::runtime(
+                    name.clone(),
+                    format!("{} isn't available without the {} capability.", name.lexeme, capability)
+                )),
+                None => Thrown::Error(error),
+            })
         }
     }
+
+    /// Creates a function for each of a class's `declarations`, closing over
+    /// the current environment. An `init` method is only an initialiser if
+    /// `can_init` is set.
+    fn functions(&self, declarations: &[Rc<stmt::Function>], can_init: bool) -> HashMap<String, Value> {
+        declarations.iter()
+            .map(|declaration| {
+                let is_init = can_init && declaration.name.lexeme == "init";
+                let function = Value::new_function(
+                    Rc::clone(declaration),
+                    Rc::clone(&self.environment),
+                    is_init
+                );
+                (declaration.name.lexeme.clone(), function)
+            })
+            .collect()
+    }
 }
 
 impl <W> Interpreter<W> {
-    pub(crate) fn resolve(&mut self, e: &Expr, depth: usize) {
-        self.locals.insert(e.clone(), depth);
+    pub(crate) fn resolve(&mut self, id: usize, depth: usize) {
+        self.locals.insert(id, depth);
     }
 }
 
 impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
     fn visit_block_stmt(&mut self, b: &stmt::Block) -> Result<()> {
-        let environment = Environment::from(&self.environment);
+        let environment = self.alloc_environment(Environment::from(&self.environment), &b.brace)?;
         self.execute_block(&b.statements, environment)
     }
 
+    fn visit_break_stmt(&mut self, _: &stmt::Break) -> Result<()> {
+        Err(Thrown::Break)
+    }
+
     fn visit_class_stmt(&mut self, c: &stmt::Class) -> Result<()> {
         let superclass = &c.superclass.as_ref()
             .map(|s| self.evaluate(s))
@@ -130,28 +505,32 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
                 }
             }).transpose()?;
 
+        self.allocate(c.name.lexeme.len() + Value::Nil.size(), &c.name)?;
+        self.take_over_constant(&c.name);
         {
             let mut env = self.environment.borrow_mut();
             env.define(&c.name.lexeme, Value::Nil);
         }
 
+        // Static methods have no instance to refer to, so they close over
+        // the class's surroundings rather than an environment with `super`.
+        let mut members = ClassMembers {
+            static_methods: self.functions(&c.static_methods, false),
+            ..ClassMembers::default()
+        };
+
         if let Some(superclass) = superclass {
             let mut new_env = Environment::from(&self.environment);
             new_env.define("super", Value::Class(superclass.clone()));
-            self.environment = Rc::new(RefCell::new(new_env));
+            self.environment = self.alloc_environment(new_env, &c.name)?;
         }
 
-        let mut methods = HashMap::new();
-        for method in c.methods.iter() {
-            let function = Value::new_function(
-                method.clone(),
-                Rc::clone(&self.environment),
-                method.name.lexeme == "init"
-            );
-            methods.insert(method.name.lexeme.clone(), function);
-        }
+        members.methods = self.functions(&c.methods, true);
+        members.getters = self.functions(&c.getters, false);
+        members.setters = self.functions(&c.setters, false);
 
-        let class = Value::new_class(&c.name.lexeme, superclass.clone(), methods);
+        let class = Value::new_class(&c.name.lexeme, superclass.clone(), members, &mut self.heap)
+            .map_err(|e| e.at(&c.name))?;
         if superclass.is_some() {
             let enclosing = self.environment.borrow()
                 .enclosing.as_ref()
@@ -163,13 +542,19 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
         Ok(())
     }
 
+    fn visit_continue_stmt(&mut self, _: &stmt::Continue) -> Result<()> {
+        Err(Thrown::Continue)
+    }
+
     fn visit_expression_stmt(&mut self, e: &stmt::Expression) -> Result<()> {
         self.evaluate(&e.expression)?;
         Ok(())
     }
 
-    fn visit_function_stmt(&mut self, f: &stmt::Function) -> Result<()> {
-        let function = Value::new_function(f.clone(), Rc::clone(&self.environment), false);
+    fn visit_function_stmt(&mut self, f: &Rc<stmt::Function>) -> Result<()> {
+        let function = Value::new_function(Rc::clone(f), Rc::clone(&self.environment), false);
+        self.allocate(f.name.lexeme.len() + function.size(), &f.name)?;
+        self.take_over_constant(&f.name);
         self.environment.borrow_mut().define(&f.name.lexeme, function);
         Ok(())
     }
@@ -178,7 +563,7 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
         if self.evaluate(&i.condition)?.is_truthy() {
             self.execute(&i.then_branch)?;
         } else if let Some(else_branch) = &i.else_branch {
-            self.execute(&else_branch)?;
+            self.execute(else_branch)?;
         }
         Ok(())
     }
@@ -196,6 +581,52 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
         Err(Thrown::Return(to_return))
     }
 
+    fn visit_throw_stmt(&mut self, t: &stmt::Throw) -> Result<()> {
+        let value = self.evaluate(&t.value)?;
+        // Throwing a caught runtime error again carries on with the
+        // original, so it's still reported where it was first raised.
+        if let Value::Instance(instance) = &value {
+            if let Some(error) = instance.origin().and_then(|origin| origin.rethrown()) {
+                return Err(Thrown::Error(error))
+            }
+        }
+        let trace = self.trace(&t.keyword);
+        Err(Thrown::Exception(Box::new(Exception { value, keyword: t.keyword.clone(), trace })))
+    }
+
+    fn visit_try_stmt(&mut self, t: &stmt::Try) -> Result<()> {
+        let environment = self.alloc_environment(Environment::from(&self.environment), &t.keyword)?;
+        let result = match (self.execute_block(&t.body, environment), &t.catch) {
+            (Err(thrown), Some((name, body))) => match self.caught(thrown) {
+                Ok(exception) => {
+                    let mut environment = Environment::from(&self.environment);
+                    environment.define(&name.lexeme, exception);
+                    let environment = self.heap.unlimited(|heap| heap.alloc(environment))
+                        .expect("Allocations without a limit always succeed.");
+                    self.execute_block(body, environment)
+                },
+                Err(thrown) => Err(thrown),
+            },
+            (result, _) => result,
+        };
+
+        // A script which has been stopped doesn't get to run its finally
+        // blocks, which could otherwise carry on regardless.
+        if let Err(Thrown::Error(error)) = &result {
+            if let ErrorKind::Limit(_) = error.kind() {
+                return result
+            }
+        }
+
+        // Anything thrown by the finally block replaces whatever happened
+        // before it.
+        if let Some(body) = &t.finally {
+            let environment = self.alloc_environment(Environment::from(&self.environment), &t.keyword)?;
+            self.execute_block(body, environment)?;
+        }
+        result
+    }
+
     fn visit_var_stmt(&mut self, v: &stmt::Var) -> Result<()> {
         let value = if let Some(initializer) = &v.initializer {
             self.evaluate(initializer)?
@@ -203,6 +634,8 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
             Value::Nil
         };
 
+        self.allocate(v.name.lexeme.len() + value.size(), &v.name)?;
+        self.take_over_constant(&v.name);
         let var_name = v.name.lexeme.clone();
 
         self.environment.borrow_mut().define(var_name, value);
@@ -211,7 +644,14 @@ impl <W: Write> stmt::Visitor<Result<()>> for Interpreter<W> {
 
     fn visit_while_stmt(&mut self, w: &stmt::While) -> Result<()> {
         while self.evaluate(&w.condition)?.is_truthy() {
-            self.execute(&w.body)?;
+            match self.execute(&w.body) {
+                Err(Thrown::Break) => break,
+                Ok(()) | Err(Thrown::Continue) => (),
+                Err(e) => return Err(e),
+            }
+            if let Some(increment) = &w.increment {
+                self.evaluate(increment)?;
+            }
         }
         Ok(())
     }
@@ -221,9 +661,10 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
     fn visit_assign_expr(&mut self, a: &expr::Assign) -> Result<Value> {
         let value = self.evaluate(&a.value)?;
 
-        if let Some(distance) = self.locals.get(&Expr::Assign(a.clone())) {
+        if let Some(distance) = self.locals.get(&a.id) {
             self.environment.borrow_mut().assign_at(*distance, &a.name, &value)?;
         } else {
+            self.check_writable(&a.name)?;
             self.globals.borrow_mut().assign(&a.name, &value)?;
         }
 
@@ -246,6 +687,7 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
                 } 
                 if let String(mut left) = left {
                     if let String(right) = right {
+                        self.allocate(left.len() + right.len(), &e.op)?;
                         left.push_str(right.as_str());
                         return Ok(String(left))
                     } 
@@ -254,6 +696,8 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
             }
             TokenKind::Slash => compute_if_numbers(&e.op, left, right, |l, r| l / r),
             TokenKind::Star => compute_if_numbers(&e.op, left, right, |l, r| l * r),
+            // The remainder takes the sign of the dividend, as in C.
+            TokenKind::Percent => compute_if_numbers(&e.op, left, right, |l, r| l % r),
             TokenKind::Greater => compute_if_numbers(&e.op, left, right, |l, r| l > r),
             TokenKind::GreaterEqual => compute_if_numbers(&e.op, left, right, |l, r| l >= r),
             TokenKind::Less => compute_if_numbers(&e.op, left, right, |l, r| l < r),
@@ -272,23 +716,34 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
             .collect::<Result<_>>()?;
 
         callee.callable()
-            .ok_or(Thrown::Error(Error::runtime(e.paren.clone(), "Can only call functions and classes.")))
+            .ok_or_else(|| Thrown::Error(
+                Error::runtime(e.paren.clone(), "Can only call functions and classes.").spanning(e.span)
+            ))
             .and_then(|c| {
-                if args.len() == c.arity() {
+                if c.arity().accepts(args.len()) {
                     Ok(c)
                 } else {
                     Err(Thrown::Error(Error::runtime(
                         e.paren.clone(),
                         format!("Expected {} arguments but got {}", c.arity(), args.len())
-                    )))
+                    ).spanning(e.span)))
                 }
             })
-            .and_then(|c| c.call(self, args))
+            .and_then(|c| self.call(c, args, &e.paren, e.span))
     }
 
     fn visit_get_expr(&mut self, g: &expr::Get) -> Result<Value> {
         match self.evaluate(&g.object)? {
-            Value::Instance(i) => i.get(&g.name).map_err(Thrown::Error),
+            Value::Instance(i) => match i.getter(&g.name.lexeme, &mut self.heap).map_err(|e| e.at(&g.name))? {
+                Some(getter) => self.call(&getter, Vec::new(), &g.name, g.span),
+                None => i.get(&g.name, &mut self.heap).map_err(Thrown::Error),
+            },
+            Value::Class(c) => c.get_static(&g.name).map_err(Thrown::Error),
+            object @ (Value::List(_) | Value::Map(_) | Value::String(_)) => BuiltinMethod::lookup(&object, &g.name.lexeme)
+                .ok_or_else(|| Thrown::Error(Error::runtime(
+                    g.name.clone(),
+                    format!("Undefined property {}.", &g.name.lexeme)
+                ))),
             _ => Err(Thrown::Error(Error::runtime(
                 g.name.clone(),
                 "Only instances have properties."
@@ -300,6 +755,40 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
         self.evaluate(&e.expression)
     }
 
+    fn visit_index_expr(&mut self, e: &expr::Index) -> Result<Value> {
+        let object = self.evaluate(&e.object)?;
+        let index = self.evaluate(&e.index)?;
+        match object {
+            Value::List(list) => {
+                let index = methods::list_index(&index, list.len())
+                    .map_err(|message| Thrown::Error(Error::runtime(e.bracket.clone(), message)))?;
+                Ok(list.get(index))
+            },
+            Value::Map(map) => {
+                let key = methods::map_key(&index)
+                    .map_err(|message| Thrown::Error(Error::runtime(e.bracket.clone(), message)))?;
+                map.0.borrow().get(&key).cloned().ok_or_else(|| Thrown::Error(Error::runtime(
+                    e.bracket.clone(),
+                    format!("Key {} is not in the map.", index)
+                )))
+            },
+            _ => Err(Thrown::Error(Error::runtime(e.bracket.clone(), "Only lists and maps can be indexed."))),
+        }
+    }
+
+    fn visit_lambda_expr(&mut self, e: &expr::Lambda) -> Result<Value> {
+        Ok(Value::new_function(Rc::clone(&e.function), Rc::clone(&self.environment), false))
+    }
+
+    fn visit_list_expr(&mut self, e: &expr::List) -> Result<Value> {
+        let elements = e.elements.iter()
+            .map(|element| self.evaluate(element))
+            .collect::<Result<_>>()?;
+        ListPointer::new(elements, &mut self.heap)
+            .map(Value::List)
+            .map_err(|oom| Thrown::Error(oom.at(&e.bracket)))
+    }
+
     fn visit_literal_expr(&mut self, e: &expr::Literal) -> Result<Value> {
         Ok(e.value.clone().into())
     }
@@ -315,11 +804,30 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
         })
     }
 
+    fn visit_map_expr(&mut self, e: &expr::Map) -> Result<Value> {
+        let mut map = Map::default();
+        for (key, value) in e.entries.iter() {
+            let key_value = self.evaluate(key)?;
+            let key = methods::map_key(&key_value)
+                .map_err(|message| Thrown::Error(Error::runtime(e.brace.clone(), message)))?;
+            map.insert(key, self.evaluate(value)?);
+        }
+        MapPointer::new(map, &mut self.heap)
+            .map(Value::Map)
+            .map_err(|oom| Thrown::Error(oom.at(&e.brace)))
+    }
+
     fn visit_set_expr(&mut self, e: &expr::Set) -> Result<Value> {
         match self.evaluate(&e.object)? {
             Value::Instance(i) => {
                 let value = self.evaluate(&e.value)?;
-                i.set(&e.name, &value);
+                match i.setter(&e.name.lexeme, &mut self.heap).map_err(|oom| oom.at(&e.name))? {
+                    Some(setter) => { self.call(&setter, vec![value.clone()], &e.name, e.span)?; },
+                    None => {
+                        self.allocate(e.name.lexeme.len() + value.size(), &e.name)?;
+                        i.set(&e.name.lexeme, &value)
+                    },
+                }
                 Ok(value)
             },
             _ => Err(Thrown::Error(Error::runtime(
@@ -329,8 +837,38 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
         }
     }
 
+    fn visit_set_index_expr(&mut self, e: &expr::SetIndex) -> Result<Value> {
+        let object = self.evaluate(&e.object)?;
+        let index = self.evaluate(&e.index)?;
+        let value = self.evaluate(&e.value)?;
+        match object {
+            Value::List(list) => {
+                let index = methods::list_index(&index, list.len())
+                    .map_err(|message| Thrown::Error(Error::runtime(e.bracket.clone(), message)))?;
+                self.allocate(value.size(), &e.bracket)?;
+                list.set(index, value.clone());
+                Ok(value)
+            },
+            Value::Map(map) => {
+                let key = methods::map_key(&index)
+                    .map_err(|message| Thrown::Error(Error::runtime(e.bracket.clone(), message)))?;
+                self.allocate(value.size(), &e.bracket)?;
+                map.0.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            },
+            _ => Err(Thrown::Error(Error::runtime(e.bracket.clone(), "Only lists and maps can be indexed."))),
+        }
+    }
+
+    fn visit_stringify_expr(&mut self, e: &expr::Stringify) -> Result<Value> {
+        match self.evaluate(&e.expression)? {
+            string @ Value::String(_) => Ok(string),
+            value => Ok(Value::String(value.to_string())),
+        }
+    }
+
     fn visit_super_expr(&mut self, e: &expr::Super) -> Result<Value> {
-        let super_instance_pair = self.locals.get(&Expr::Super(e.clone())).and_then(|dist| {
+        let super_instance_pair = self.locals.get(&e.id).and_then(|dist| {
             let env = self.environment.borrow();
             let sc = env.maybe_get_at(*dist, "super");
             let i = env.maybe_get_at(*dist - 1, "this");
@@ -340,19 +878,24 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
             }
         });
         if let Some((Value::Class(sup), Value::Instance(this))) = super_instance_pair {
-            if let Some(Value::Function(method)) = sup.get_field(&e.method.lexeme) {
-                Ok(Value::Function(method.binding(this)))
-            } else {
-                Err(Thrown::Error(Error::runtime(
+            match (sup.get_field(&e.method.lexeme), sup.get_getter(&e.method.lexeme)) {
+                (Some(Value::Function(method)), _) => method.binding(this, &mut self.heap)
+                    .map(Value::Function)
+                    .map_err(|oom| Thrown::Error(oom.at(&e.method))),
+                (_, Some(Value::Function(getter))) => {
+                    let getter = getter.binding(this, &mut self.heap).map_err(|oom| oom.at(&e.method))?;
+                    self.call(&getter, Vec::new(), &e.method, e.span)
+                },
+                _ => Err(Thrown::Error(Error::runtime(
                     e.method.clone(),
                     format!("Undefined property {}.", &e.method.lexeme)
-                )))
+                ))),
             }
         } else { unreachable!("super was set incorrectly. This is a static analysis bug.") }
     }
 
     fn visit_this_expr(&mut self, e: &expr::This) -> Result<Value> {
-        self.lookup_variable(&e.keyword, &Expr::This(e.clone()))
+        self.lookup_variable(&e.keyword, e.id)
     }
 
     fn visit_unary_expr(&mut self, e: &expr::Unary) -> Result<Value> {
@@ -369,7 +912,7 @@ impl <W: Write> expr::Visitor<Result<Value>> for Interpreter<W> {
     }
 
     fn visit_variable_expr(&mut self, e: &expr::Variable) -> Result<Value> {
-        self.lookup_variable(&e.name, &Expr::Variable(e.clone()))
+        self.lookup_variable(&e.name, e.id)
     }
 }
 
@@ -1,11 +1,24 @@
-mod callable;
+pub mod callable;
 pub mod error;
 mod environment;
 mod expr;
+mod gc;
+pub mod host;
+mod methods;
+pub mod natives;
 pub mod interpreter;
+pub mod limits;
+pub mod lox;
 pub mod parser;
 pub mod resolver;
 pub mod scanner;
-mod stmt;
-mod token;
-mod value;
\ No newline at end of file
+pub mod stmt;
+pub mod token;
+pub mod value;
+
+pub use gc::{GcStats, MemoryUsage};
+pub use host::{Recording, TimeSource};
+pub use limits::{CancelHandle, Limits};
+pub use natives::Capabilities;
+pub use lox::Lox;
+pub use value::Value;
\ No newline at end of file
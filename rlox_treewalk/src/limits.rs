@@ -0,0 +1,60 @@
+use std::{
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Bounds on how much work a script may do before it's stopped. A script
+/// which exceeds one of these fails with an error that `catch` can't
+/// intercept, so it can't carry on regardless.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// The most statements and expressions which can be evaluated, counted
+    /// from when the limits were set.
+    pub max_steps: Option<u64>,
+    /// The time by which execution must have finished.
+    pub deadline: Option<Instant>,
+    /// Stops execution once it's been cancelled.
+    pub cancel: Option<CancelHandle>,
+}
+
+/// The limit which stopped a script.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Steps,
+    Deadline,
+    Cancelled,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps => write!(f, "Step limit exceeded."),
+            Limit::Deadline => write!(f, "Deadline exceeded."),
+            Limit::Cancelled => write!(f, "Execution cancelled."),
+        }
+    }
+}
+
+/// A flag which stops a running script when it's set. Clones share the same
+/// flag, so one can be kept by the host - on another thread, say - while the
+/// other is given to the interpreter.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,968 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, Display},
+    io::Write,
+    rc::Rc,
+    thread,
+};
+
+use crate::{
+    callable::Arity,
+    error::{self, Error, WithSource},
+    gc::{GcStats, MemoryUsage},
+    host::{Recording, TimeSource},
+    interpreter::Interpreter,
+    limits::Limits,
+    natives::Capabilities,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    token::Token,
+    value::Value,
+};
+
+pub type RunResult<T> = std::result::Result<T, RunError>;
+
+/// The errors produced by a failed run, tagged with the stage of the
+/// pipeline which produced them. Every stage before the interpreter
+/// recovers from errors, so they can report more than one at a time.
+#[derive(Debug)]
+pub enum RunError {
+    Scan(Vec<Error>),
+    Parse(Vec<Error>),
+    Resolve(Vec<Error>),
+    Runtime(Error),
+}
+
+impl RunError {
+    pub fn errors(&self) -> &[Error] {
+        use RunError::*;
+        match self {
+            Scan(errors) | Parse(errors) | Resolve(errors) => errors,
+            Runtime(e) => std::slice::from_ref(e),
+        }
+    }
+
+    /// Wraps the errors so that each displays with a snippet of `source`.
+    pub fn with_source<'a>(&'a self, source: &'a str) -> WithSource<'a, RunError> {
+        WithSource { inner: self, source }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_lines(f, self.errors().iter())
+    }
+}
+
+impl Display for WithSource<'_, RunError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_lines(f, self.inner.errors().iter().map(|e| e.with_source(self.source)))
+    }
+}
+
+fn write_lines<T: Display>(f: &mut fmt::Formatter<'_>, mut lines: impl Iterator<Item = T>) -> fmt::Result {
+    if let Some(first) = lines.next() {
+        write!(f, "{}", first)?;
+    }
+    for line in lines {
+        write!(f, "\n{}", line)?;
+    }
+    Ok(())
+}
+
+/// A Lox session which can be embedded in a host program. Globals defined
+/// by one call to `run_source` are visible to every later call, which is
+/// what makes the REPL work.
+pub struct Lox<W> {
+    interpreter: Rc<RefCell<Interpreter<W>>>,
+    resolver: Resolver<W>,
+    warnings: Vec<Error>,
+    // The id the next source's first node gets, so that no two nodes the
+    // interpreter sees share one.
+    next_node_id: usize,
+}
+
+impl <W: Write> Lox<W> {
+    /// Creates an interpreter which can't reach the host at all. See
+    /// `with_capabilities`.
+    pub fn new(out: W) -> Self {
+        Self::with_capabilities(out, Capabilities::default())
+    }
+
+    pub fn with_capabilities(out: W, capabilities: Capabilities) -> Self {
+        let interpreter = Rc::new(RefCell::new(Interpreter::new(out, capabilities)));
+        let resolver = Resolver::new(&interpreter);
+        Self { interpreter, resolver, warnings: Vec::new(), next_node_id: 0 }
+    }
+
+    pub fn run_source(&mut self, source: &str) -> RunResult<()> {
+        self.warnings.clear();
+        let tokens = scan(source)?;
+
+        let mut parser = Parser::with_first_id(tokens.into_iter(), self.next_node_id);
+        let (statements, errors): (Vec<_>, Vec<_>) = parser.parse().into_iter().partition(Result::is_ok);
+        self.next_node_id = parser.next_id();
+
+        let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
+        if !errors.is_empty() {
+            return Err(RunError::Parse(errors))
+        }
+        let statements: Vec<_> = statements.into_iter().map(Result::unwrap).collect();
+
+        let errors = self.resolver.resolve(&statements);
+        self.warnings = self.resolver.take_warnings();
+        if !errors.is_empty() {
+            return Err(RunError::Resolve(errors))
+        }
+
+        self.interpreter.borrow_mut()
+            .interpret(&statements)
+            .map_err(RunError::Runtime)
+    }
+
+    pub fn eval_expression(&mut self, source: &str) -> RunResult<Value> {
+        self.warnings.clear();
+        let tokens = scan(source)?;
+
+        let mut parser = Parser::with_first_id(tokens.into_iter(), self.next_node_id);
+        let expression = parser.parse_expression();
+        self.next_node_id = parser.next_id();
+        let expression = expression.map_err(|e| RunError::Parse(vec![e]))?;
+
+        let errors = self.resolver.resolve_expression(&expression);
+        self.warnings = self.resolver.take_warnings();
+        if !errors.is_empty() {
+            return Err(RunError::Resolve(errors))
+        }
+
+        self.interpreter.borrow_mut()
+            .interpret_expression(&expression)
+            .map_err(RunError::Runtime)
+    }
+
+    /// The warnings found while resolving the most recent source. They
+    /// never stop a program from running.
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    /// Runs a garbage collection now, returning how many objects it freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.interpreter.borrow_mut().collect_garbage()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.interpreter.borrow().gc_stats()
+    }
+
+    /// See `Interpreter::set_time_source`.
+    pub fn set_time_source<T: TimeSource + 'static>(&mut self, time: T) {
+        self.interpreter.borrow_mut().set_time_source(time)
+    }
+
+    /// See `Interpreter::seed_random`.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.interpreter.borrow_mut().seed_random(seed)
+    }
+
+    /// See `Interpreter::record`.
+    pub fn record(&mut self) {
+        self.interpreter.borrow_mut().record()
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.interpreter.borrow_mut().take_recording()
+    }
+
+    /// See `Interpreter::replay`.
+    pub fn replay(&mut self, recording: Recording) {
+        self.interpreter.borrow_mut().replay(recording)
+    }
+
+    /// See `Interpreter::set_memory_limit`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.interpreter.borrow_mut().set_memory_limit(limit)
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.interpreter.borrow().memory_usage()
+    }
+
+    /// See `Interpreter::set_max_call_depth`.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.interpreter.borrow_mut().set_max_call_depth(depth)
+    }
+
+    /// See `Interpreter::set_limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.borrow_mut().set_limits(limits)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.interpreter.borrow().get_global(name)
+    }
+
+    pub fn set_global<S: Into<String>, V: Into<Value>>(&mut self, name: S, value: V) {
+        self.interpreter.borrow_mut().set_global(name, value.into())
+    }
+
+    pub fn define_native<S, F>(&mut self, name: S, arity: Arity, body: F)
+    where
+        S: Into<String>,
+        F: Fn(&mut Interpreter<W>, &[Value]) -> error::Result<Value> + 'static,
+    {
+        self.interpreter.borrow_mut().define_native(name, arity, body)
+    }
+}
+
+/// Runs `f` on a new thread with a stack of `size` bytes, waiting for it to
+/// finish. This allows a much higher maximum call depth than the stack of the
+/// thread it's called from would. The interpreter can't move between threads,
+/// so any `Lox` has to be created inside `f`.
+pub fn with_stack_size<F, T>(size: usize, f: F) -> T
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    thread::scope(|scope| {
+        let thread = thread::Builder::new()
+            .stack_size(size)
+            .spawn_scoped(scope, f)
+            .expect("Failed to spawn a thread for the interpreter");
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+fn scan(source: &str) -> RunResult<Vec<Token>> {
+    let (tokens, errors): (Vec<_>, Vec<_>) = Scanner::new(source).partition(Result::is_ok);
+
+    let errors: Vec<_> = errors.into_iter().map(Result::unwrap_err).collect();
+    if !errors.is_empty() {
+        return Err(RunError::Scan(errors))
+    }
+
+    Ok(tokens.into_iter().map(Result::unwrap).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ErrorKind,
+        limits::{CancelHandle, Limit},
+    };
+    use std::time::{Duration, Instant};
+
+    fn stopped_by<W: Write>(lox: &mut Lox<W>, source: &str) -> Option<Limit> {
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => match e.kind() {
+                ErrorKind::Limit(limit) => Some(*limit),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn globals_persist_between_runs() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("var a = 1;").unwrap();
+        lox.run_source("a = a + 2;").unwrap();
+        assert_eq!(Some(Value::Number(3.0)), lox.get_global("a"));
+    }
+
+    #[test]
+    fn host_can_set_globals() {
+        let mut lox = Lox::new(Vec::new());
+        lox.set_global("greeting", "Hello");
+        lox.run_source("var message = greeting + \", world\";").unwrap();
+        assert_eq!(Some(Value::from("Hello, world")), lox.get_global("message"));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("fun double(n) { return n * 2; }").unwrap();
+        assert_eq!(Value::Number(42.0), lox.eval_expression("double(20) + 2").unwrap());
+    }
+
+    #[test]
+    fn print_writes_to_output() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("print \"hi\";").unwrap();
+        assert_eq!(b"hi\n".to_vec(), out);
+    }
+
+    #[test]
+    fn errors_are_tagged_with_their_stage() {
+        let mut lox = Lox::new(Vec::new());
+        assert!(matches!(lox.run_source("var a = @;"), Err(RunError::Scan(_))));
+        assert!(matches!(lox.run_source("var = 1;"), Err(RunError::Parse(_))));
+        assert!(matches!(lox.run_source("return 1;"), Err(RunError::Resolve(_))));
+        assert!(matches!(lox.run_source("-\"a\";"), Err(RunError::Runtime(_))));
+        assert!(matches!(lox.eval_expression("1 2"), Err(RunError::Parse(_))));
+    }
+
+    #[test]
+    fn warnings_only_come_from_the_latest_source() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("fun f() { var unused; }").unwrap();
+        assert_eq!(1, lox.warnings().len());
+        assert!(matches!(lox.run_source("var = 1;"), Err(RunError::Parse(_))));
+        assert!(lox.warnings().is_empty());
+        lox.run_source("fun g() { var unused; }").unwrap();
+        assert!(matches!(lox.eval_expression("@"), Err(RunError::Scan(_))));
+        assert!(lox.warnings().is_empty());
+    }
+
+    #[test]
+    fn identical_expressions_resolve_independently() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("var total = 0; for (var i = 0; i < 3; i = i + 1) { total = total + i; }").unwrap();
+        assert_eq!(Some(Value::Number(3.0)), lox.get_global("total"));
+    }
+
+    #[test]
+    fn errors_can_be_shown_with_their_source() {
+        let mut lox = Lox::new(Vec::new());
+        let source = "var f = 1;\nprint 1 + f(2);";
+        let error = lox.run_source(source).unwrap_err();
+        assert_eq!(
+            [
+                "[line 2] Error at ): Can only call functions and classes.",
+                " --> line 2, column 11",
+                "  |",
+                "2 | print 1 + f(2);",
+                "  |           ^^^^",
+            ].join("\n"),
+            error.with_source(source).to_string()
+        );
+    }
+
+    #[test]
+    fn lexical_errors_underline_the_bad_character() {
+        let mut lox = Lox::new(Vec::new());
+        let source = "var a = @;";
+        let error = lox.run_source(source).unwrap_err();
+        assert!(error.with_source(source).to_string().ends_with("1 | var a = @;\n  |         ^"));
+    }
+
+    #[test]
+    fn errors_shown_with_the_wrong_source_leave_out_the_snippet() {
+        let mut lox = Lox::new(Vec::new());
+        let error = lox.run_source("var a = 1;\nvar b = \"é\" + @;").unwrap_err();
+        for source in ["", "var a = 1;\nvar b = ééééé;"] {
+            assert_eq!(error.to_string(), error.with_source(source).to_string());
+        }
+    }
+
+    #[test]
+    fn natives_can_be_variadic() {
+        let mut lox = Lox::new(Vec::new());
+        lox.define_native("sum", Arity::AtLeast(1), |_, args| {
+            args.iter().try_fold(0.0, |total, arg| match arg {
+                Value::Number(n) => Ok(total + n),
+                _ => Err(Error::native("sum only accepts numbers.")),
+            }).map(Value::Number)
+        });
+        assert_eq!(Value::Number(6.0), lox.eval_expression("sum(1, 2, 3)").unwrap());
+        assert_eq!(Value::Number(1.0), lox.eval_expression("sum(1)").unwrap());
+        assert!(matches!(lox.eval_expression("sum()"), Err(RunError::Runtime(_))));
+    }
+
+    #[test]
+    fn natives_can_capture_host_state() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut lox = Lox::new(Vec::new());
+        let log = Rc::clone(&calls);
+        lox.define_native("log", Arity::Exactly(1), move |_, args| {
+            log.borrow_mut().push(args[0].clone());
+            Ok(Value::Nil)
+        });
+        lox.run_source("log(1); log(\"two\");").unwrap();
+        assert_eq!(vec![Value::Number(1.0), Value::from("two")], *calls.borrow());
+    }
+
+    #[test]
+    fn natives_only_run_in_the_interpreter_which_defined_them() {
+        let mut first = Lox::new(Vec::new());
+        first.define_native("answer", Arity::Exactly(0), |_, _| Ok(Value::Number(42.0)));
+        let answer = first.get_global("answer").unwrap();
+
+        let mut second = Lox::new(Vec::new());
+        second.set_global("answer", answer);
+        match second.run_source("answer();") {
+            Err(RunError::Runtime(e)) => assert_eq!("answer was defined by a different interpreter.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn native_errors_are_reported_at_the_call_site() {
+        let mut lox = Lox::new(Vec::new());
+        lox.define_native("fail", Arity::Exactly(0), |_, _| Err(Error::native("Nope.")));
+        match lox.run_source("\n\nfail();") {
+            Err(RunError::Runtime(e)) => assert_eq!("[line 3] Error at ): Nope.", e.to_string()),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("
+            class Node {}
+            fun make() { var n = Node(); n.me = n; }
+            make(); make();
+        ").unwrap();
+        assert_eq!(2, lox.collect_garbage());
+        assert_eq!(0, lox.collect_garbage());
+        assert_eq!(2, lox.gc_stats().collections);
+        assert_eq!(2, lox.gc_stats().freed);
+    }
+
+    #[test]
+    fn keeps_reachable_cycles() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("class Node {} var keep = Node(); keep.me = keep; keep.value = 1;").unwrap();
+        assert_eq!(0, lox.collect_garbage());
+        assert_eq!(Value::Number(1.0), lox.eval_expression("keep.me.me.value").unwrap());
+    }
+
+    #[test]
+    fn collects_automatically_without_losing_values_in_use() {
+        let mut lox = Lox::new(Vec::new());
+        lox.run_source("
+            class Pair { init(a, b) { this.a = a; this.b = b; this.me = this; } }
+            fun sum(p, q) { return p.a + q.b; }
+            var total = 0;
+            for (var i = 0; i < 2000; i = i + 1) { total = total + sum(Pair(i, 0), Pair(0, 1)); }
+        ").unwrap();
+        assert!(lox.gc_stats().collections > 0);
+        assert!(lox.gc_stats().freed > 0);
+        assert_eq!(Some(Value::Number(1999.0 * 2000.0 / 2.0 + 2000.0)), lox.get_global("total"));
+    }
+
+    #[test]
+    fn loops_can_break_and_continue() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            for (var i = 0; i < 10; i = i + 1) {
+                if (i == 1) continue;
+                if (i == 4) break;
+                var j = 0;
+                while (true) { j = j + 1; if (j > i) break; }
+                print j;
+            }
+        ").unwrap();
+        assert_eq!(b"1\n3\n4\n".to_vec(), out);
+    }
+
+    #[test]
+    fn lists_can_be_indexed_and_modified() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            var xs = [1, 2, 3];
+            xs[0] = xs[1] + xs[2];
+            xs.push(4);
+            xs.insert(0, \"first\");
+            print xs.remove(1);
+            print xs.pop();
+            print xs.len();
+            print xs;
+        ").unwrap();
+        assert_eq!(b"5\n4\n3\n[first, 2, 3]\n".to_vec(), out);
+    }
+
+    #[test]
+    fn list_indices_are_checked() {
+        let mut lox = Lox::new(Vec::new());
+        match lox.run_source("var xs = [1, 2, 3];\nprint xs[5];") {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 2] Error at [: Index 5 is out of range for a list of length 3.",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn lists_which_contain_themselves_can_be_printed() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.run_source("
+            var xs = [1];
+            xs.push(xs);
+            print xs;
+            print [xs, xs];").unwrap();
+        drop(lox);
+        assert_eq!("[1, [...]]\n[[1, [...]], [1, [...]]]\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn maps_keep_their_keys_in_insertion_order() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            var m = {\"a\": 1, 2: \"two\", nil: false};
+            m[true] = m[\"a\"] + 1;
+            m[2] = \"again\";
+            print m.remove(\"a\");
+            print m.has(\"a\");
+            print m.keys();
+            print m.values();
+            print m.len();
+            print m;
+        ").unwrap();
+        assert_eq!(b"1\nfalse\n[2, nil, true]\n[again, false, 2]\n3\n{2: again, nil: false, true: 2}\n".to_vec(), out);
+    }
+
+    #[test]
+    fn map_keys_must_be_present_and_hashable() {
+        let mut lox = Lox::new(Vec::new());
+        match lox.run_source("var m = {};\nprint m[\"missing\"];") {
+            Err(RunError::Runtime(e)) => assert_eq!("[line 2] Error at [: Key missing is not in the map.", e.to_string()),
+            _ => panic!("Expected a runtime error."),
+        }
+        match lox.run_source("m[[]] = 1;") {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 1] Error at [: Map keys must be strings, numbers, booleans or nil.",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn maps_which_contain_themselves_can_be_printed() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.run_source("
+            var m = {};
+            m[\"self\"] = m;
+            m[\"list\"] = [m];
+            print m;").unwrap();
+        drop(lox);
+        assert_eq!("{self: {...}, list: [{...}]}\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn functions_can_be_anonymous() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            fun apply(f, x) { return f(x); }
+            var offset = 10;
+            print apply(fun (n) { return n + offset; }, 5);
+            var add = fun (a, b) { return a + b; };
+            print add;
+            print add(1, 2);
+        ").unwrap();
+        assert_eq!(b"15\n<fn anonymous>\n3\n".to_vec(), out);
+    }
+
+    #[test]
+    fn classes_can_have_getters_setters_and_static_methods() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            class Shape {
+                class unit() { return Square(1); }
+            }
+            class Square < Shape {
+                init(side) { this.side = side; }
+                area { return this.side * this.side; }
+                width { return this.side; }
+                width=(value) { this.side = value; }
+            }
+            var s = Square.unit();
+            print s.area;
+            s.width = 3;
+            print s.width;
+            print s.area;
+        ").unwrap();
+        assert_eq!(b"1\n3\n9\n".to_vec(), out);
+    }
+
+    #[test]
+    fn getters_can_be_reached_through_super() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            class A { g { return this.base; } }
+            class B < A {
+                init() { this.base = 1; }
+                g { return super.g + 1; }
+            }
+            print B().g;
+        ").unwrap();
+        assert_eq!(b"2\n".to_vec(), out);
+    }
+
+    #[test]
+    fn exceptions_can_be_caught() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            fun check(n) {
+                if (n > 1) throw \"too big\";
+                return n;
+            }
+            try {
+                print check(1);
+                print check(2);
+            } catch (e) {
+                print e;
+            } finally {
+                print \"done\";
+            }
+            try { nil + 1; } catch (e) { print e.message; print e.line; }
+            fun early() { try { return 1; } finally { print \"cleanup\"; } }
+            print early();
+        ").unwrap();
+        assert_eq!(
+            "1\ntoo big\ndone\nOperands must be two numbers or two strings.\n14\ncleanup\n1\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn errors_are_objects_scripts_can_make_and_catch() {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source("
+            try { nil + 1; } catch (e) { print e; }
+            class NotFound < Error {}
+            try { throw NotFound(\"missing\"); } catch (e) { print e; print e.line; }
+            print Error;
+        ").unwrap();
+        assert_eq!(
+            "Operands must be two numbers or two strings.\nmissing\nnil\nError\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn rethrown_errors_keep_their_original_trace() {
+        let mut lox = Lox::new(Vec::new());
+        let source = "
+            fun fail() { nil + 1; }
+            fun retry() {
+                try { fail(); } catch (e) {
+                    throw e;
+                }
+            }
+            retry();";
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 2] Error at +: Operands must be two numbers or two strings.\n\
+                 [line 2] in fail()\n\
+                 [line 4] in retry()\n\
+                 [line 8] in script",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn uncaught_exceptions_show_a_stack_trace() {
+        let mut lox = Lox::new(Vec::new());
+        let source = "
+            fun inner() { throw \"oops\"; }
+            fun outer() {
+                inner();
+            }
+            outer();";
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 2] Error at throw: Uncaught exception: oops\n\
+                 [line 2] in inner()\n\
+                 [line 4] in outer()\n\
+                 [line 6] in script",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn runtime_errors_show_a_stack_trace() {
+        let mut lox = Lox::new(Vec::new());
+        let source = "
+            class Counter {
+                add(n) { return this.count + n; }
+            }
+            fun run(counter) {
+                for (var i = 0; i < 2; i = i + 1) counter.add(i);
+            }
+            run(Counter());";
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => {
+                assert_eq!(
+                    "[line 3] Error at count: Undefined property count.\n\
+                     [line 3] in add()\n\
+                     [line 6] in run()\n\
+                     [line 8] in script",
+                    e.to_string()
+                );
+                assert_eq!(Some("add"), e.trace()[0].function.as_deref());
+            },
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn deep_recursion_is_a_catchable_error() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.set_max_call_depth(50);
+        let source = "
+            fun recurse(n) { return recurse(n + 1); }
+            try { recurse(0); } catch (e) { print e.message; }
+            recurse(0);";
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 2] Error at ): Stack overflow.\n\
+                 [line 2] in recurse()\n\
+                 [Previous line repeated 49 more times]\n\
+                 [line 4] in script",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+        drop(lox);
+        assert_eq!("Stack overflow.\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn deep_recursion_can_run_on_a_larger_stack() {
+        let out = with_stack_size(256 * 1024 * 1024, || {
+            let mut out = Vec::new();
+            let mut lox = Lox::new(&mut out);
+            lox.set_max_call_depth(10_000);
+            lox.run_source("
+                fun count(n) { if (n == 0) return 0; return count(n - 1) + 1; }
+                print count(5000);").unwrap();
+            drop(lox);
+            out
+        });
+        assert_eq!("5000\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn accessors_are_calls() {
+        let mut lox = Lox::new(Vec::new());
+        lox.set_max_call_depth(20);
+        let source = "
+            class A {
+                x { return this.x; }
+                y=(value) { this.y = value; }
+            }
+            try { print A().x; } catch (e) { print e.message; }
+            A().y = 1;";
+        match lox.run_source(source) {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 4] Error at y: Stack overflow.\n\
+                 [line 4] in y()\n\
+                 [Previous line repeated 19 more times]\n\
+                 [line 7] in script",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn scripts_stop_when_they_run_out_of_steps() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        let source = "try { while (true) {} } catch (e) { print \"caught\"; }";
+        assert_eq!(Some(Limit::Steps), stopped_by(&mut lox, source));
+
+        // Setting the limits again starts a fresh budget.
+        lox.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        lox.run_source("print 1 + 2;").unwrap();
+        drop(lox);
+        assert_eq!("3\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn scripts_stop_at_their_deadline() {
+        let mut lox = Lox::new(Vec::new());
+        let deadline = Instant::now() + Duration::from_millis(20);
+        lox.set_limits(Limits { deadline: Some(deadline), ..Limits::default() });
+        assert_eq!(Some(Limit::Deadline), stopped_by(&mut lox, "while (true) {}"));
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn finally_blocks_cant_outlast_a_deadline() {
+        let mut lox = Lox::new(Vec::new());
+        let deadline = Instant::now() + Duration::from_millis(20);
+        lox.set_limits(Limits { deadline: Some(deadline), ..Limits::default() });
+        let script = "while (true) { try { while (true) {} } finally { continue; } }";
+        assert_eq!(Some(Limit::Deadline), stopped_by(&mut lox, script));
+        assert_eq!(Some(Limit::Deadline), stopped_by(&mut lox, "print 1;"));
+    }
+
+    #[test]
+    fn scripts_can_be_cancelled_from_another_thread() {
+        let cancel = CancelHandle::new();
+        let mut lox = Lox::new(Vec::new());
+        lox.set_limits(Limits { cancel: Some(cancel.clone()), ..Limits::default() });
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        assert_eq!(Some(Limit::Cancelled), stopped_by(&mut lox, "fun spin() { while (true) {} } spin();"));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn running_out_of_memory_is_a_catchable_error() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.set_memory_limit(Some(64 * 1024));
+        lox.run_source("
+            var s = \"x\";
+            try {
+                while (true) s = s + s;
+            } catch (e) {
+                print e.message;
+            }
+            var list = [];
+            try {
+                while (true) list.push(s);
+            } catch (e) {
+                print e.line;
+                list = nil;
+            }
+            print \"recovered\" + \"!\";").unwrap();
+        let usage = lox.memory_usage();
+        assert!(usage.current <= 64 * 1024);
+        assert!(usage.peak > 32 * 1024);
+        drop(lox);
+        assert_eq!("Out of memory.\n10\nrecovered!\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn running_out_of_memory_can_be_caught_with_none_to_spare() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.set_memory_limit(Some(64 * 1024));
+        lox.run_source("
+            var list = [];
+            try {
+                while (true) list.push(1);
+            } catch (e) {
+                list = nil;
+                print e.message;
+            }").unwrap();
+        drop(lox);
+        assert_eq!("Out of memory.\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn memory_usage_is_counted() {
+        let mut lox = Lox::new(Vec::new());
+        let before = lox.memory_usage().current;
+        lox.run_source("
+            class Box {}
+            var boxes = [];
+            for (var i = 0; i < 100; i = i + 1) {
+                var b = Box();
+                b.label = \"box ${i}\";
+                boxes.push(b);
+            }").unwrap();
+        let during = lox.memory_usage();
+        assert!(during.current > before + 100 * std::mem::size_of::<Value>());
+
+        lox.run_source("boxes = nil;").unwrap();
+        lox.collect_garbage();
+        let after = lox.memory_usage();
+        assert!(after.current < during.current);
+        assert_eq!(during.peak, after.peak);
+    }
+
+    #[test]
+    fn every_allocation_is_limited() {
+        let scripts = [
+            "var xs = []; while (true) xs = [xs, xs];",
+            "var m = {}; while (true) m = {\"inner\": m, \"again\": {\"m\": m}};",
+            "var parts = []; while (true) parts.push(\"a b c\".split(\" \"));",
+            "var m = {\"a\": 1}; var all = []; while (true) all.push(m.keys());",
+            "var words = []; while (true) words.push(\"word\".upper());",
+        ];
+        for script in scripts {
+            let mut lox = Lox::new(Vec::new());
+            lox.set_memory_limit(Some(64 * 1024));
+            match lox.run_source(script) {
+                Err(RunError::Runtime(e)) => assert_eq!("Out of memory.", e.message(), "{}", script),
+                _ => panic!("Expected {} to run out of memory.", script),
+            }
+            assert!(lox.memory_usage().peak <= 64 * 1024, "{}", script);
+        }
+    }
+
+    #[test]
+    fn time_and_random_numbers_can_be_made_deterministic() {
+        let run = |seed| {
+            let mut out = Vec::new();
+            let mut lox = Lox::with_capabilities(&mut out, Capabilities { time: true, ..Capabilities::default() });
+            let mut now = 1000.0;
+            lox.set_time_source(move || { now += 1.0; now });
+            lox.seed_random(seed);
+            lox.run_source("
+                print clock();
+                print clock();
+                var r = random();
+                print r >= 0 and r < 1;
+                print r;").unwrap();
+            drop(lox);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(run(7), run(7));
+        assert!(run(7).starts_with("1001\n1002\ntrue\n"));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn runs_can_be_recorded_and_replayed() {
+        let source = "print clock(); print random(); print getEnv(\"RLOX_UNSET_VARIABLE\");";
+
+        let mut recorded = Vec::new();
+        let mut lox = Lox::with_capabilities(&mut recorded, Capabilities::all());
+        lox.seed_random(1);
+        lox.record();
+        lox.run_source(source).unwrap();
+        let recording = lox.take_recording().unwrap();
+        drop(lox);
+        assert_eq!(3, recording.inputs.len());
+
+        let mut replayed = Vec::new();
+        let mut lox = Lox::with_capabilities(&mut replayed, Capabilities::all());
+        lox.set_time_source(|| 0.0);
+        lox.seed_random(2);
+        lox.replay(recording);
+        lox.run_source(source).unwrap();
+        match lox.run_source("print clock();") {
+            Err(RunError::Runtime(e)) => assert_eq!("Replay has no input left for clock.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        drop(lox);
+        assert_eq!(String::from_utf8(recorded).unwrap(), String::from_utf8(replayed).unwrap());
+    }
+
+    #[test]
+    fn remainders_take_the_sign_of_the_dividend() {
+        let mut out = Vec::new();
+        let mut lox = Lox::new(&mut out);
+        lox.run_source("print 7 % 3 + -7 % 3 * 10;").unwrap();
+        match lox.run_source("print 1 % nil;") {
+            Err(RunError::Runtime(e)) => assert_eq!("Operands must be numbers.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        drop(lox);
+        assert_eq!("-9\n", String::from_utf8(out).unwrap());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,304 @@
+use std::mem;
+
+use crate::{
+    callable::Arity,
+    error::{Error, Result},
+    gc::Heap,
+    token::HashableNumber,
+    value::{ListPointer, Literal, MapPointer, Value},
+};
+
+const LIST_METHODS: &[(&str, Arity)] = &[
+    ("insert", Arity::Exactly(2)),
+    ("len", Arity::Exactly(0)),
+    ("pop", Arity::Exactly(0)),
+    ("push", Arity::Exactly(1)),
+    ("remove", Arity::Exactly(1)),
+];
+
+const MAP_METHODS: &[(&str, Arity)] = &[
+    ("has", Arity::Exactly(1)),
+    ("keys", Arity::Exactly(0)),
+    ("len", Arity::Exactly(0)),
+    ("remove", Arity::Exactly(1)),
+    ("values", Arity::Exactly(0)),
+];
+
+const STRING_METHODS: &[(&str, Arity)] = &[
+    ("indexOf", Arity::Exactly(1)),
+    ("len", Arity::Exactly(0)),
+    ("lower", Arity::Exactly(0)),
+    ("replace", Arity::Exactly(2)),
+    ("split", Arity::Exactly(1)),
+    ("substring", Arity::Exactly(2)),
+    ("trim", Arity::Exactly(0)),
+    ("upper", Arity::Exactly(0)),
+];
+
+/// A method built into one of Lox's native types, bound to the value it was
+/// looked up on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuiltinMethod {
+    pub(crate) receiver: Box<Value>,
+    pub(crate) name: &'static str,
+    pub(crate) arity: Arity,
+}
+
+impl BuiltinMethod {
+    /// Looks up the method `name` on `receiver`, if its type has one.
+    pub(crate) fn lookup(receiver: &Value, name: &str) -> Option<Value> {
+        let methods = match receiver {
+            Value::List(_) => LIST_METHODS,
+            Value::Map(_) => MAP_METHODS,
+            Value::String(_) => STRING_METHODS,
+            _ => return None,
+        };
+        methods.iter()
+            .find(|(method, _)| *method == name)
+            .map(|&(name, arity)| Value::BuiltinMethod(BuiltinMethod {
+                receiver: Box::new(receiver.clone()),
+                name,
+                arity,
+            }))
+    }
+
+    pub(crate) fn call_with(&self, args: &[Value], heap: &mut Heap) -> Result<Value> {
+        match self.receiver.as_ref() {
+            Value::List(list) => call_list_method(list, self.name, args, heap),
+            Value::Map(map) => call_map_method(map, self.name, args, heap),
+            Value::String(string) => call_string_method(string, self.name, args, heap),
+            _ => unreachable!("Builtin methods are only looked up on types which have them."),
+        }
+    }
+}
+
+fn call_list_method(list: &ListPointer, name: &str, args: &[Value], heap: &mut Heap) -> Result<Value> {
+    match (name, args) {
+        ("insert", [index, value]) => {
+            // Inserting at the very end is the same as pushing.
+            let len = list.len();
+            let index = match index {
+                Value::Number(n) if *n == len as f64 => len,
+                _ => list_index(index, len).map_err(Error::native)?,
+            };
+            heap.grow(value.size())?;
+            list.0.borrow_mut().insert(index, value.clone());
+            Ok(Value::Nil)
+        },
+        ("len", []) => Ok(Value::Number(list.len() as f64)),
+        ("pop", []) => list.0.borrow_mut()
+            .pop()
+            .ok_or_else(|| Error::native("Can't pop from an empty list.")),
+        ("push", [value]) => {
+            heap.grow(value.size())?;
+            list.0.borrow_mut().push(value.clone());
+            Ok(Value::Nil)
+        },
+        ("remove", [index]) => {
+            let index = list_index(index, list.len()).map_err(Error::native)?;
+            Ok(list.0.borrow_mut().remove(index))
+        },
+        _ => unreachable!("Arity is checked before builtin methods are called."),
+    }
+}
+
+fn call_map_method(map: &MapPointer, name: &str, args: &[Value], heap: &mut Heap) -> Result<Value> {
+    match (name, args) {
+        ("has", [key]) => {
+            let key = map_key(key).map_err(Error::native)?;
+            Ok(Value::Bool(map.0.borrow().get(&key).is_some()))
+        },
+        ("keys", []) => {
+            let keys = map.0.borrow().keys().cloned().map(Value::from).collect();
+            Ok(Value::List(ListPointer::new(keys, heap)?))
+        },
+        ("len", []) => Ok(Value::Number(map.0.borrow().len() as f64)),
+        ("remove", [key]) => {
+            let key = map_key(key).map_err(Error::native)?;
+            Ok(map.0.borrow_mut().remove(&key).unwrap_or(Value::Nil))
+        },
+        ("values", []) => {
+            let values = map.0.borrow().values().cloned().collect();
+            Ok(Value::List(ListPointer::new(values, heap)?))
+        },
+        _ => unreachable!("Arity is checked before builtin methods are called."),
+    }
+}
+
+/// Strings are indexed by character rather than byte, so that they can be
+/// sliced anywhere without splitting a character in two.
+///
+/// Results are reserved on the heap before they're built, so that a script
+/// can't use a small string to make the host build a huge one.
+fn call_string_method(string: &str, name: &str, args: &[Value], heap: &mut Heap) -> Result<Value> {
+    let result = match (name, args) {
+        ("indexOf", [needle]) => {
+            let needle = string_arg(name, needle)?;
+            let index = string.find(needle)
+                .map(|byte| string[..byte].chars().count() as f64)
+                .unwrap_or(-1.0);
+            return Ok(Value::Number(index))
+        },
+        ("len", []) => return Ok(Value::Number(string.chars().count() as f64)),
+        ("lower", []) => {
+            reserve_string(heap, string.chars().flat_map(char::to_lowercase).map(char::len_utf8).sum())?;
+            string.to_lowercase()
+        },
+        ("replace", [from, to]) => {
+            let (from, to) = (string_arg(name, from)?, string_arg(name, to)?);
+            // Replacing "" inserts `to` between every character, as well as
+            // at either end.
+            let matches = if from.is_empty() { string.chars().count() + 1 } else { string.matches(from).count() };
+            let len = (string.len() - matches * from.len()).saturating_add(matches.saturating_mul(to.len()));
+            reserve_string(heap, len)?;
+            string.replace(from, to)
+        },
+        ("split", [separator]) => {
+            let separator = string_arg(name, separator)?;
+            let parts = if separator.is_empty() { string.chars().count() } else { string.matches(separator).count() + 1 };
+            heap.reserve(mem::size_of::<Vec<Value>>() + parts * mem::size_of::<Value>() + string.len())?;
+            // An empty separator splits the string into its characters.
+            let parts: Vec<_> = if separator.is_empty() {
+                string.chars().map(|c| Value::String(c.to_string())).collect()
+            } else {
+                string.split(separator).map(Value::from).collect()
+            };
+            return Ok(Value::List(ListPointer::new(parts, heap)?))
+        },
+        ("substring", [start, end]) => {
+            let len = string.chars().count();
+            let start = string_index(start, len).map_err(Error::native)?;
+            let end = string_index(end, len).map_err(Error::native)?;
+            if start > end {
+                return Err(Error::native(format!("Substring start {} is after its end {}.", start, end)))
+            }
+            let substring: &str = match (string.char_indices().nth(start), string.char_indices().nth(end)) {
+                (Some((start, _)), Some((end, _))) => &string[start..end],
+                (Some((start, _)), None) => &string[start..],
+                _ => "",
+            };
+            reserve_string(heap, substring.len())?;
+            substring.to_string()
+        },
+        ("trim", []) => {
+            let trimmed = string.trim();
+            reserve_string(heap, trimmed.len())?;
+            trimmed.to_string()
+        },
+        ("upper", []) => {
+            reserve_string(heap, string.chars().flat_map(char::to_uppercase).map(char::len_utf8).sum())?;
+            string.to_uppercase()
+        },
+        _ => unreachable!("Arity is checked before builtin methods are called."),
+    };
+    let result = Value::String(result);
+    heap.grow(result.size())?;
+    Ok(result)
+}
+
+fn reserve_string(heap: &mut Heap, len: usize) -> Result<()> {
+    Ok(heap.reserve(mem::size_of::<Value>() + len)?)
+}
+
+fn string_arg<'a>(method: &str, arg: &'a Value) -> Result<&'a str> {
+    match arg {
+        Value::String(s) => Ok(s),
+        _ => Err(Error::native(format!("Arguments to {} must be strings.", method))),
+    }
+}
+
+/// Converts `index` to a position in a string of length `len`, which can be
+/// anywhere from the start to the very end.
+fn string_index(index: &Value, len: usize) -> std::result::Result<usize, String> {
+    match index {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) <= len => Ok(*n as usize),
+        Value::Number(n) => Err(format!("Index {} is out of range for a string of length {}.", n, len)),
+        _ => Err("String indices must be numbers.".to_string()),
+    }
+}
+
+/// Converts `index` to a position in a list of length `len`, or describes
+/// why it isn't one.
+pub(crate) fn list_index(index: &Value, len: usize) -> std::result::Result<usize, String> {
+    match index {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => Ok(*n as usize),
+        Value::Number(n) => Err(format!("Index {} is out of range for a list of length {}.", n, len)),
+        _ => Err("List indices must be numbers.".to_string()),
+    }
+}
+
+/// Converts `key` to the form maps store their keys in, or describes why
+/// it can't be one.
+pub(crate) fn map_key(key: &Value) -> std::result::Result<Literal, String> {
+    match key {
+        Value::Bool(b) => Ok(Literal::Bool(*b)),
+        Value::Nil => Ok(Literal::Nil),
+        Value::Number(n) if n.is_nan() => Err("NaN can't be used as a map key.".to_string()),
+        // Keys are hashed by their bits, so -0 has to be stored as 0 for
+        // the two to find the same entry.
+        Value::Number(n) if *n == 0.0 => Ok(Literal::Number(HashableNumber(0.0))),
+        Value::Number(n) => Ok(Literal::Number(HashableNumber(*n))),
+        Value::String(s) => Ok(Literal::String(s.clone())),
+        _ => Err("Map keys must be strings, numbers, booleans or nil.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lox::RunError, Lox};
+
+    /// Runs `source` in a fresh interpreter, returning what it printed.
+    fn run(source: &str) -> String {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source(source).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn strings_have_methods() {
+        assert_eq!("12\nWÖRLD\n6\nheLLo, wörLd\n[Hello, wörld]\n[a, b, c]\n", run("
+            var s = \"  Hello, wörld  \".trim();
+            print s.len();
+            print s.substring(7, 12).upper();
+            print s.indexOf(\"wö\") + s.indexOf(\"x\");
+            print s.lower().replace(\"l\", \"L\");
+            print s.split(\", \");
+            print \"abc\".split(\"\");"));
+        let mut lox = Lox::new(Vec::new());
+        match lox.run_source("\"abc\".substring(2, 1);") {
+            Err(RunError::Runtime(e)) => assert_eq!("Substring start 2 is after its end 1.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        match lox.run_source("\"abc\".substring(0, 4);") {
+            Err(RunError::Runtime(e)) => assert_eq!("Index 4 is out of range for a string of length 3.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        match lox.run_source("\"abc\".missing;") {
+            Err(RunError::Runtime(e)) => assert_eq!("Undefined property missing.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn string_results_are_limited_before_theyre_built() {
+        let mut lox = Lox::new(Vec::new());
+        lox.set_memory_limit(Some(1024 * 1024));
+        lox.run_source("var s = \"x\"; for (var i = 0; i < 19; i = i + 1) s = s + s;").unwrap();
+        let calls = [
+            "s.substring(0, 32768).replace(\"\", s.substring(0, 32768))",
+            "s.replace(\"x\", \"xx\")",
+            "s.upper()",
+            "s.lower()",
+            "s.split(\"\")",
+            "s.substring(0, s.len())",
+            "s.trim()",
+        ];
+        for call in calls {
+            match lox.run_source(&format!("{};", call)) {
+                Err(RunError::Runtime(e)) => assert_eq!("Out of memory.", e.message(), "{}", call),
+                _ => panic!("Expected {} to run out of memory.", call),
+            }
+            assert!(lox.memory_usage().peak <= 1024 * 1024, "{}", call);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,376 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    callable::Arity,
+    error::{Error, Result},
+    host::Host,
+    interpreter::Interpreter,
+    scanner,
+    value::Value,
+};
+
+/// The parts of the host which scripts are allowed to reach. Nothing is
+/// granted by default, so an interpreter can only compute and print unless
+/// the host opts in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    /// `clock()`
+    pub time: bool,
+    /// `readLine()`
+    pub stdin: bool,
+    /// `readFile(path)`
+    pub fs_read: bool,
+    /// `writeFile(path, contents)`
+    pub fs_write: bool,
+    /// `getEnv(name)`
+    pub env: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities { time: true, stdin: true, fs_read: true, fs_write: true, env: true }
+    }
+
+    fn grants(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Time => self.time,
+            Capability::Stdin => self.stdin,
+            Capability::FsRead => self.fs_read,
+            Capability::FsWrite => self.fs_write,
+            Capability::Env => self.env,
+        }
+    }
+}
+
+/// A single one of the `Capabilities`, named after its field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Capability {
+    Time,
+    Stdin,
+    FsRead,
+    FsWrite,
+    Env,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Time => "time",
+            Capability::Stdin => "stdin",
+            Capability::FsRead => "fs_read",
+            Capability::FsWrite => "fs_write",
+            Capability::Env => "env",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+type Native = fn(&mut Host, &[Value]) -> Result<Value>;
+
+/// Whether a native reaches the host, so its result has to be recorded for
+/// a run to be replayed, and it mustn't run again during the replay.
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Host,
+    Script,
+}
+
+/// Defines every native which `capabilities` allows, returning the names
+/// of the ones it doesn't along with what they need.
+pub(crate) fn define_natives<W: Write>(
+    interpreter: &mut Interpreter<W>,
+    capabilities: Capabilities,
+) -> Vec<(&'static str, Capability)> {
+    let natives: [(&str, Arity, Option<Capability>, Source, Native); 8] = [
+        ("clock", Arity::Exactly(0), Some(Capability::Time), Source::Host, clock),
+        ("getEnv", Arity::Exactly(1), Some(Capability::Env), Source::Host, get_env),
+        ("num", Arity::Exactly(1), None, Source::Script, num),
+        ("random", Arity::Exactly(0), None, Source::Host, random),
+        ("readFile", Arity::Exactly(1), Some(Capability::FsRead), Source::Host, read_file),
+        ("readLine", Arity::Exactly(0), Some(Capability::Stdin), Source::Host, read_line),
+        ("str", Arity::Exactly(1), None, Source::Script, str),
+        ("writeFile", Arity::Exactly(2), Some(Capability::FsWrite), Source::Host, write_file),
+    ];
+
+    let mut denied = Vec::new();
+    for (name, arity, capability, source, body) in natives {
+        match capability {
+            Some(capability) if !capabilities.grants(capability) => denied.push((name, capability)),
+            _ if source == Source::Host => interpreter.define_native(name, arity, move |interpreter, args| {
+                interpreter.host.read(name, |host| body(host, args))
+            }),
+            _ => interpreter.define_native(name, arity, move |interpreter, args| body(&mut interpreter.host, args)),
+        }
+    }
+    define_math(interpreter);
+    denied
+}
+
+type UnaryFn = fn(f64) -> f64;
+type BinaryFn = fn(f64, f64) -> f64;
+
+const UNARY_MATH: &[(&str, UnaryFn)] = &[
+    ("abs", f64::abs),
+    ("acos", f64::acos),
+    ("asin", f64::asin),
+    ("atan", f64::atan),
+    ("ceil", f64::ceil),
+    ("cos", f64::cos),
+    ("exp", f64::exp),
+    ("floor", f64::floor),
+    ("log", f64::ln),
+    ("round", f64::round),
+    ("sin", f64::sin),
+    ("sqrt", f64::sqrt),
+    ("tan", f64::tan),
+    ("trunc", f64::trunc),
+];
+
+const BINARY_MATH: &[(&str, BinaryFn)] = &[
+    ("atan2", f64::atan2),
+    ("pow", f64::powf),
+];
+
+const MATH_CONSTANTS: &[(&str, f64)] = &[
+    ("E", std::f64::consts::E),
+    ("INFINITY", f64::INFINITY),
+    ("NAN", f64::NAN),
+    ("PI", std::f64::consts::PI),
+];
+
+/// Defines the math functions and constants, which are pure and so need no
+/// capabilities.
+fn define_math<W: Write>(interpreter: &mut Interpreter<W>) {
+    for &(name, f) in UNARY_MATH {
+        interpreter.define_native(name, Arity::Exactly(1), move |_, args| {
+            Ok(Value::Number(f(number_arg(name, &args[0])?)))
+        });
+    }
+    for &(name, f) in BINARY_MATH {
+        interpreter.define_native(name, Arity::Exactly(2), move |_, args| {
+            Ok(Value::Number(f(number_arg(name, &args[0])?, number_arg(name, &args[1])?)))
+        });
+    }
+    for (name, pick) in [("max", f64::max as BinaryFn), ("min", f64::min)] {
+        interpreter.define_native(name, Arity::AtLeast(1), move |_, args| {
+            let first = number_arg(name, &args[0])?;
+            args[1..].iter().try_fold(first, |acc, arg| Ok(pick(acc, number_arg(name, arg)?))).map(Value::Number)
+        });
+    }
+    for &(name, value) in MATH_CONSTANTS {
+        interpreter.define_constant(name, Value::Number(value));
+    }
+}
+
+fn number_arg(native: &str, arg: &Value) -> Result<f64> {
+    match arg {
+        Value::Number(n) => Ok(*n),
+        _ => Err(Error::native(format!("Arguments to {} must be numbers.", native))),
+    }
+}
+
+fn clock(host: &mut Host, _: &[Value]) -> Result<Value> {
+    Ok(Value::Number(host.now()))
+}
+
+/// A random number between 0 (inclusive) and 1 (exclusive). The generator
+/// always starts from the same seed unless the host seeds it.
+fn random(host: &mut Host, _: &[Value]) -> Result<Value> {
+    Ok(Value::Number(host.random()))
+}
+
+/// Parses a string written the same way as a number literal, which may be
+/// surrounded by whitespace and negated by a `-` directly in front of it.
+fn num(_: &mut Host, args: &[Value]) -> Result<Value> {
+    let text = string_arg(&args[0], "num can only convert strings.")?;
+    let trimmed = text.trim();
+    let number = match trimmed.strip_prefix('-') {
+        Some(unsigned) => scanner::number_literal(unsigned).map(|n| -n),
+        None => scanner::number_literal(trimmed),
+    };
+    number
+        .map(Value::Number)
+        .ok_or_else(|| Error::native(format!("Can't convert \"{}\" to a number.", text)))
+}
+
+fn str(_: &mut Host, args: &[Value]) -> Result<Value> {
+    Ok(Value::String(args[0].to_string()))
+}
+
+fn get_env(_: &mut Host, args: &[Value]) -> Result<Value> {
+    let name = string_arg(&args[0], "Environment variable names must be strings.")?;
+    Ok(std::env::var(name).map(Value::String).unwrap_or(Value::Nil))
+}
+
+fn read_file(_: &mut Host, args: &[Value]) -> Result<Value> {
+    let path = string_arg(&args[0], "Paths must be strings.")?;
+    std::fs::read_to_string(path)
+        .map(Value::String)
+        .map_err(|e| Error::native(format!("Couldn't read {}: {}", path, e)))
+}
+
+/// Reads a line from stdin without its line ending, or returns nil at the
+/// end of the input.
+fn read_line(_: &mut Host, _: &[Value]) -> Result<Value> {
+    let mut line = String::new();
+    let read = io::stdin().lock()
+        .read_line(&mut line)
+        .map_err(|e| Error::native(format!("Couldn't read from stdin: {}", e)))?;
+    if read == 0 {
+        return Ok(Value::Nil)
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Value::String(line))
+}
+
+fn write_file(_: &mut Host, args: &[Value]) -> Result<Value> {
+    let path = string_arg(&args[0], "Paths must be strings.")?;
+    let contents = string_arg(&args[1], "File contents must be a string.")?;
+    std::fs::write(path, contents)
+        .map(|_| Value::Nil)
+        .map_err(|e| Error::native(format!("Couldn't write {}: {}", path, e)))
+}
+
+fn string_arg<'a>(arg: &'a Value, message: &str) -> Result<&'a str> {
+    match arg {
+        Value::String(s) => Ok(s),
+        _ => Err(Error::native(message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{host::Input, lox::RunError, Lox};
+
+    use super::*;
+
+    /// Runs `source` in a fresh interpreter, returning what it printed.
+    fn run(source: &str) -> String {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source(source).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn math_constants_are_read_only() {
+        match Lox::new(Vec::new()).run_source("PI = 3;") {
+            Err(RunError::Runtime(e)) => assert_eq!("Can't assign to constant.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        assert_eq!("4\ntrue\n", run("{ var PI = 3; PI = 4; print PI; } print PI == 3.141592653589793;"));
+    }
+
+    #[test]
+    fn scripts_can_declare_their_own_constants() {
+        assert_eq!("3\n<fn NAN>\nINFINITY\n", run("
+            var E = 2;
+            E = 3;
+            print E;
+            fun NAN() {}
+            print NAN;
+            class INFINITY {}
+            print INFINITY;"));
+    }
+
+    #[test]
+    fn replays_skip_writing_files() {
+        let path = std::env::temp_dir().join(format!("rlox-replay-{}.txt", std::process::id()));
+        let source = format!("print writeFile(\"{}\", \"recorded\");", path.display());
+
+        let mut lox = Lox::with_capabilities(Vec::new(), Capabilities::all());
+        lox.record();
+        lox.run_source(&source).unwrap();
+        let recording = lox.take_recording().unwrap();
+        assert_eq!(vec![("writeFile".to_string(), Input::Nil)], recording.inputs);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut out = Vec::new();
+        let mut lox = Lox::with_capabilities(&mut out, Capabilities::all());
+        lox.replay(recording);
+        lox.run_source(&source).unwrap();
+        drop(lox);
+        assert_eq!("nil\n", String::from_utf8(out).unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn natives_need_their_capabilities() {
+        let mut lox = Lox::new(Vec::new());
+        assert_eq!(None, lox.get_global("clock"));
+        match lox.run_source("clock();") {
+            Err(RunError::Runtime(e)) => assert_eq!("clock isn't available without the time capability.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+
+        let mut out = Vec::new();
+        let capabilities = Capabilities { time: true, ..Capabilities::default() };
+        let mut lox = Lox::with_capabilities(&mut out, capabilities);
+        lox.run_source("print clock() > 0;").unwrap();
+        match lox.run_source("getEnv(\"HOME\");") {
+            Err(RunError::Runtime(e)) => assert_eq!("getEnv isn't available without the env capability.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+        drop(lox);
+        assert_eq!("true\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn files_can_be_written_and_read_back() {
+        let path = std::env::temp_dir().join(format!("rlox-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let mut out = Vec::new();
+        let mut lox = Lox::with_capabilities(&mut out, Capabilities::all());
+        lox.run_source(&format!("
+            writeFile(\"{0}\", \"written by lox\");
+            print readFile(\"{0}\");", path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        drop(lox);
+        assert_eq!("written by lox\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn math_natives_work_on_numbers() {
+        assert_eq!("8\n1024\n5\n100\ntrue\n", run("
+            print sqrt(16) + abs(-2) * floor(2.7);
+            print pow(2, 10);
+            print min(3, 1, 2) + max(4);
+            print round(sin(PI / 2) * 100);
+            print -INFINITY < E;"));
+        match Lox::new(Vec::new()).run_source("sqrt(\"16\");") {
+            Err(RunError::Runtime(e)) => assert_eq!(
+                "[line 1] Error at ): Arguments to sqrt must be numbers.",
+                e.to_string()
+            ),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn values_convert_to_and_from_strings() {
+        assert_eq!("1.5nil[true]\n13.5\n2013\n", run("
+            print str(1.5) + str(nil) + str([true]);
+            print num(\" -2.5\\n\") + num(\"16\");
+            print num(\"1e3\") + num(\"0x10\") + num(\"-0b11\") + num(\"1_000\");"));
+        let mut lox = Lox::new(Vec::new());
+        let rejected = [
+            "12abc", "", "1 2", "1 // comment", "/* 1 */ 2", "- 5", "--5", "+5",
+            "inf", "-infinity", "nan", ".5", "5.", "0x",
+        ];
+        for text in rejected {
+            match lox.run_source(&format!("num(\"{}\");", text)) {
+                Err(RunError::Runtime(e)) => {
+                    assert_eq!(format!("Can't convert \"{}\" to a number.", text), e.message())
+                },
+                _ => panic!("Expected num to reject {:?}.", text),
+            }
+        }
+        match lox.run_source("num(12);") {
+            Err(RunError::Runtime(e)) => assert_eq!("num can only convert strings.", e.message()),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+}
\ No newline at end of file
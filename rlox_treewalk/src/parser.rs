@@ -1,4 +1,4 @@
-use std::iter::Peekable;
+use std::{iter::Peekable, rc::Rc};
 
 use crate::{
     error::{Error, Result},
@@ -8,41 +8,60 @@ use crate::{
     value,
 };
 
-const EQUALITY_TOKENS: &'static [&'static TokenKind] = &[
+const EQUALITY_TOKENS: &[&TokenKind] = &[
     &TokenKind::BangEqual, 
     &TokenKind::EqualEqual,
 ];
 
-const COMPARISON_TOKENS: &'static [&'static TokenKind] = &[
+const COMPARISON_TOKENS: &[&TokenKind] = &[
     &TokenKind::Greater, 
     &TokenKind::GreaterEqual, 
     &TokenKind::Less, 
     &TokenKind::LessEqual,
 ];
 
-const TERM_TOKENS: &'static [&'static TokenKind] = &[
+const TERM_TOKENS: &[&TokenKind] = &[
     &TokenKind::Minus,
     &TokenKind::Plus,
 ];
 
-const FACTOR_TOKENS: &'static [&'static TokenKind] = &[
+const FACTOR_TOKENS: &[&TokenKind] = &[
     &TokenKind::Star, 
     &TokenKind::Slash,
+    &TokenKind::Percent,
 ];
 
-const UNARY_TOKENS: &'static [&'static TokenKind] = &[
+const UNARY_TOKENS: &[&TokenKind] = &[
     &TokenKind::Bang,
     &TokenKind:: Minus,
 ];
 
 pub struct Parser<T> {
     tokens: T,
+    // The span of the most recently consumed token.
+    previous: Span,
+    // The id the next node parsed will get.
+    next_id: usize,
 }
 
 impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
     pub fn new(tokens: T) -> Self {
+        Self::with_first_id(tokens, 0)
+    }
+
+    /// Creates a parser whose nodes get ids from `first_id` on. Ids only
+    /// need to be unique within a single interpreter, but a REPL session
+    /// runs many separate programs in one, so each program has to carry on
+    /// from where the last one's ids left off.
+    pub fn with_first_id(tokens: T, first_id: usize) -> Self {
         let tokens = tokens.peekable();
-        Parser { tokens }
+        Parser { tokens, previous: Span::default(), next_id: first_id }
+    }
+
+    /// The id the next node parsed will get, which is where the parser for
+    /// the next program run by the same interpreter should start from.
+    pub fn next_id(&self) -> usize {
+        self.next_id
     }
 
     pub fn parse(&mut self) -> Vec<Result<Stmt>> {
@@ -53,15 +72,28 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         statements
     }
 
+    pub(crate) fn parse_expression(&mut self) -> Result<Expr> {
+        let expression = self.expression()?;
+        match self.advance() {
+            None => Ok(expression),
+            Some(t) if t.kind == TokenKind::EndOfFile => Ok(expression),
+            Some(t) => Err(Error::syntactic(t, "Expected end of expression.")),
+        }
+    }
+
     fn declaration(&mut self) -> Option<Result<Stmt>> {
-        if self.tokens.peek() == None { return None }
-
-        let result = if self.match_single(&TokenKind::Class).is_some() {
-            self.class_declaration()
-        } else if self.match_single(&TokenKind::Fun).is_some() {
-            self.function("function").map(|f| Stmt::Function(f))
-        } else if self.match_single(&TokenKind::Var).is_some() {
-            self.var_declaration()
+        self.tokens.peek()?;
+
+        let result = if let Some(keyword) = self.match_single(&TokenKind::Class) {
+            self.class_declaration(keyword)
+        } else if let Some(keyword) = self.match_single(&TokenKind::Fun) {
+            self.function("function").map(|mut f| {
+                f.span = keyword.span.to(f.span);
+                f.doc = keyword.doc;
+                Stmt::Function(Rc::new(f))
+            })
+        } else if let Some(keyword) = self.match_single(&TokenKind::Var) {
+            self.var_declaration(keyword)
         } else {
             self.statement()
         };
@@ -73,27 +105,58 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         Some(result)
     }
 
-    fn class_declaration(&mut self) -> Result<Stmt> {
+    fn class_declaration(&mut self, keyword: Token) -> Result<Stmt> {
         let name = self.consume(&TokenKind::Identifier, "Expected class name.")?;
 
         let superclass = if self.match_single(&TokenKind::Less).is_some() {
             let name = self.consume(&TokenKind::Identifier, "Expected superclass name.")?;
-            Some(Expr::new_variable(name))
+            Some(Expr::new_variable(self.new_id(), name.span, name))
         } else { None };
 
         self.consume(&TokenKind::LeftBrace, "Expected '{' before class body.")?;
 
-        let mut methods = Vec::new();
+        let (mut methods, mut getters, mut setters, mut static_methods) = (vec![], vec![], vec![], vec![]);
         while self.tokens.peek().map(|t| &t.kind) != Some(&TokenKind::RightBrace) {
-            methods.push(self.function("method")?);
+            if let Some(keyword) = self.match_single(&TokenKind::Class) {
+                let mut method = self.function("static method")?;
+                method.span = keyword.span.to(method.span);
+                method.doc = keyword.doc;
+                static_methods.push(Rc::new(method));
+                continue
+            }
+
+            let name = self.consume(&TokenKind::Identifier, "Expected method name")?;
+            if self.match_single(&TokenKind::Equal).is_some() {
+                self.consume(&TokenKind::LeftParen, "Expected '(' after setter name.")?;
+                let setter = self.function_body(name, "setter")?;
+                if setter.params.len() != 1 {
+                    return Err(Error::syntactic(setter.name, "A setter must have exactly one parameter."))
+                }
+                setters.push(Rc::new(setter));
+            } else if self.check_next(&TokenKind::LeftBrace) {
+                getters.push(Rc::new(self.getter(name)?));
+            } else {
+                self.consume(&TokenKind::LeftParen, "Expected '(' after method name.")?;
+                methods.push(Rc::new(self.function_body(name, "method")?));
+            }
         }
 
         self.consume(&TokenKind::RightBrace, "Expected '}' after class body.")?;
 
-        Ok(Stmt::new_class(name, superclass, methods))
+        let span = self.span_from(&keyword);
+        let doc = keyword.doc;
+        Ok(Stmt::new_class(self.new_id(), span, name, superclass, methods, getters, setters, static_methods, doc))
+    }
+
+    /// Parses the body of a getter, which has no parameter list.
+    fn getter(&mut self, name: Token) -> Result<stmt::Function> {
+        self.consume(&TokenKind::LeftBrace, "Expect '{' before getter body.")?;
+        let body = self.block()?;
+        let doc = name.doc.clone();
+        Ok(stmt::Function { id: self.new_id(), span: self.span_from(&name), name, params: Vec::new(), body, doc })
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt> {
+    fn var_declaration(&mut self, keyword: Token) -> Result<Stmt> {
         let name = self.consume(&TokenKind::Identifier, "Expected variable name.")?;
 
         let initializer = if self.match_single(&TokenKind::Equal).is_some() {
@@ -103,22 +166,35 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         };
 
         self.consume(&TokenKind::Semicolon, "Expected ';' after variable declaration.")?;
-        Ok(Stmt::new_var(name, initializer))
+        Ok(Stmt::new_var(self.new_id(), self.span_from(&keyword), name, initializer, keyword.doc.clone()))
     }
 
     fn statement(&mut self) -> Result<Stmt> {
-        if self.match_single(&TokenKind::For).is_some() {
-            self.for_statement()
-        } else if self.match_single(&TokenKind::If).is_some() {
-            self.if_statement()
-        } else if self.match_single(&TokenKind::Print).is_some() {
-            self.print_statement()
+        if let Some(keyword) = self.match_single(&TokenKind::For) {
+            self.for_statement(keyword)
+        } else if let Some(keyword) = self.match_single(&TokenKind::If) {
+            self.if_statement(keyword)
+        } else if let Some(keyword) = self.match_single(&TokenKind::Print) {
+            self.print_statement(keyword)
+        } else if let Some(keyword) = self.match_single(&TokenKind::Break) {
+            self.consume(&TokenKind::Semicolon, "Expected ';' after 'break'.")?;
+            Ok(Stmt::new_break(self.new_id(), self.span_from(&keyword), keyword))
+        } else if let Some(keyword) = self.match_single(&TokenKind::Continue) {
+            self.consume(&TokenKind::Semicolon, "Expected ';' after 'continue'.")?;
+            Ok(Stmt::new_continue(self.new_id(), self.span_from(&keyword), keyword))
         } else if let Some(token) = self.match_single(&TokenKind::Return) {
             self.return_statement(token)
-        } else if self.match_single(&TokenKind::While).is_some() {
-            self.while_statement()
-        } else if self.match_single(&TokenKind::LeftBrace).is_some() {
-            Ok(Stmt::new_block(self.block()?))
+        } else if let Some(keyword) = self.match_single(&TokenKind::Throw) {
+            let value = self.expression()?;
+            self.consume(&TokenKind::Semicolon, "Expected ';' after thrown value.")?;
+            Ok(Stmt::new_throw(self.new_id(), self.span_from(&keyword), keyword, value))
+        } else if let Some(keyword) = self.match_single(&TokenKind::Try) {
+            self.try_statement(keyword)
+        } else if let Some(keyword) = self.match_single(&TokenKind::While) {
+            self.while_statement(keyword)
+        } else if let Some(brace) = self.match_single(&TokenKind::LeftBrace) {
+            let statements = self.block()?;
+            Ok(Stmt::new_block(self.new_id(), self.span_from(&brace), brace, statements))
         } else if self.tokens.peek().is_some() {
             self.expression_statement()
         } else {
@@ -126,44 +202,47 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         }
     }
 
-    fn for_statement(&mut self) -> Result<Stmt> {
+    fn for_statement(&mut self, keyword: Token) -> Result<Stmt> {
         self.consume(&TokenKind::LeftParen, "Expected '(' after 'for'.")?;
 
         let initializer = if self.match_single(&TokenKind::Semicolon).is_some() {
             None
-        } else if self.match_single(&TokenKind::Var).is_some() {
-            Some(self.var_declaration()?)
+        } else if let Some(var) = self.match_single(&TokenKind::Var) {
+            Some(self.var_declaration(var)?)
         } else {
             Some(self.expression_statement()?)
         };
 
         let condition = if !self.check_next(&TokenKind::Semicolon) {
-            self.expression()?
-        } else { Expr::new_literal(value::Literal::from(true)) };
+            Some(self.expression()?)
+        } else { None };
 
         self.consume(&TokenKind::Semicolon, "Expected ';' after loop condition.")?;
 
         let increment = if !self.check_next(&TokenKind::RightParen) {
-            Some(Stmt::new_expression(self.expression()?))
+            Some(self.expression()?)
         } else { None };
 
         self.consume(&TokenKind::RightParen, "Expected ')' after for clauses.")?;
 
         let body = self.statement()?;
-        let body = Box::new(match increment {
-            Some(i) => Stmt::new_block(vec![body, i]),
-            None => body,
-        });
-        let while_loop = Stmt::new_while(condition, body);
+
+        // The desugared nodes don't exist in the source, so they all
+        // take the span of the whole loop.
+        let span = self.span_from(&keyword);
+        let condition = condition.unwrap_or_else(||
+            Expr::new_literal(self.new_id(), span, value::Literal::from(true))
+        );
+        let while_loop = Stmt::new_while(self.new_id(), span, condition, Box::new(body), increment);
         let while_loop = match initializer {
-            Some(i) => Stmt::new_block(vec![i, while_loop]),
+            Some(i) => Stmt::new_block(self.new_id(), span, keyword, vec![i, while_loop]),
             None => while_loop,
         };
 
         Ok(while_loop)
     }
 
-    fn if_statement(&mut self) -> Result<Stmt> {
+    fn if_statement(&mut self, keyword: Token) -> Result<Stmt> {
         self.consume(&TokenKind::LeftParen, "Expected '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(&TokenKind::RightParen, "Expected ')' after if condition.")?;
@@ -173,13 +252,13 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
             Some(Box::new(self.statement()?))
         } else { None };
 
-        Ok(Stmt::new_if(condition, then_branch, else_branch))
+        Ok(Stmt::new_if(self.new_id(), self.span_from(&keyword), condition, then_branch, else_branch))
     }
 
-    fn print_statement(&mut self) -> Result<Stmt> {
+    fn print_statement(&mut self, keyword: Token) -> Result<Stmt> {
         let expression = self.expression()?;
         self.consume(&TokenKind::Semicolon, "Expected ';' after expression.")?;
-        Ok(Stmt::new_print(expression))
+        Ok(Stmt::new_print(self.new_id(), self.span_from(&keyword), expression))
     }
 
     fn return_statement(&mut self, token: Token) -> Result<Stmt> {
@@ -187,22 +266,46 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
             Some(self.expression()?)
         } else { None };
         self.consume(&TokenKind::Semicolon, "Expected ';' after return value.")?;
-        Ok(Stmt::new_return(token, return_value))
+        Ok(Stmt::new_return(self.new_id(), self.span_from(&token), token, return_value))
     }
 
-    fn while_statement(&mut self) -> Result<Stmt> {
+    fn try_statement(&mut self, keyword: Token) -> Result<Stmt> {
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let catch = if self.match_single(&TokenKind::Catch).is_some() {
+            self.consume(&TokenKind::LeftParen, "Expected '(' after 'catch'.")?;
+            let name = self.consume(&TokenKind::Identifier, "Expected exception name.")?;
+            self.consume(&TokenKind::RightParen, "Expected ')' after exception name.")?;
+            self.consume(&TokenKind::LeftBrace, "Expected '{' before catch body.")?;
+            Some((name, self.block()?))
+        } else { None };
+
+        let finally = if self.match_single(&TokenKind::Finally).is_some() {
+            self.consume(&TokenKind::LeftBrace, "Expected '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else { None };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(Error::syntactic(keyword, "Expected 'catch' or 'finally' after try block."))
+        }
+        Ok(Stmt::new_try(self.new_id(), self.span_from(&keyword), keyword, body, catch, finally))
+    }
+
+    fn while_statement(&mut self, keyword: Token) -> Result<Stmt> {
         self.consume(&TokenKind::LeftParen, "Expected '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(&TokenKind::RightParen, "Expected ')' after condition.")?;
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::new_while(condition, body))
+        Ok(Stmt::new_while(self.new_id(), self.span_from(&keyword), condition, body, None))
     }
 
     fn expression_statement(&mut self) -> Result<Stmt> {
         let expression = self.expression()?;
         self.consume(&TokenKind::Semicolon, "Expected ';' after expression.")?;
-        Ok(Stmt::new_expression(expression))
+        let span = expression.span().to(self.previous);
+        Ok(Stmt::new_expression(self.new_id(), span, expression))
     }
 
     fn function(&mut self, kind: &str) -> Result<stmt::Function> {
@@ -214,7 +317,12 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
             &TokenKind::LeftParen,
             format!("Expected '(' after {} name.", kind).as_str()
         )?;
+        self.function_body(name, kind)
+    }
 
+    /// Parses the parameters and body of a function, the '(' before its
+    /// parameters having already been consumed.
+    fn function_body(&mut self, name: Token, kind: &str) -> Result<stmt::Function> {
         let mut params = Vec::new();
         if !self.check_next(&TokenKind::RightParen) {
             loop {
@@ -234,7 +342,11 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
             format!("Expect '{{' before {} body.", kind).as_str()
         )?;
 
-        Ok(stmt::Function { name, params, body: self.block()? })
+        let body = self.block()?;
+        // Methods have no keyword, so their doc comment is attached to their
+        // name. Function declarations replace this with the one before `fun`.
+        let doc = name.doc.clone();
+        Ok(stmt::Function { id: self.new_id(), span: self.span_from(&name), name, params, body, doc })
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>> {
@@ -259,9 +371,13 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         let expr = self.or()?;
         if let Some(equals) = self.match_single(&TokenKind::Equal) {
             let value = self.assignment()?;
+            let span = expr.span().to(*value.span());
             match expr {
-                Expr::Variable(lhs) => Ok(Expr::new_assign(lhs.name, Box::new(value))),
-                Expr::Get(lhs) => Ok(Expr::new_set(lhs.object, lhs.name, Box::new(value))),
+                Expr::Variable(lhs) => Ok(Expr::new_assign(self.new_id(), span, lhs.name, Box::new(value))),
+                Expr::Get(lhs) => Ok(Expr::new_set(self.new_id(), span, lhs.object, lhs.name, Box::new(value))),
+                Expr::Index(lhs) => Ok(
+                    Expr::new_set_index(self.new_id(), span, lhs.object, lhs.bracket, lhs.index, Box::new(value))
+                ),
                  // N.b. in jlox this error doesn't throw - it just returns
                 // the expr we already parsed on the lhs. This is inconvenient
                 // with rlox's current error-handling. I'm also not sure the
@@ -279,7 +395,8 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
 
         while let Some(op) = self.match_single(&TokenKind::Or) {
             let right = Box::new(self.and()?);
-            e = Expr::new_logical(Box::new(e), op, right);
+            let span = e.span().to(*right.span());
+            e = Expr::new_logical(self.new_id(), span, Box::new(e), op, right);
         }
 
         Ok(e)
@@ -290,7 +407,8 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
 
         while let Some(op) = self.match_single(&TokenKind::And) {
             let right = Box::new(self.equality()?);
-            e = Expr::new_logical(Box::new(e), op, right);
+            let span = e.span().to(*right.span());
+            e = Expr::new_logical(self.new_id(), span, Box::new(e), op, right);
         }
 
         Ok(e)
@@ -327,7 +445,8 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
     fn unary(&mut self) -> Result<Expr> {
         if let Some(token) = self.match_any(UNARY_TOKENS) {
             let right = Box::new(self.unary()?);
-            Ok(Expr::new_unary(token, right))
+            let span = token.span.to(*right.span());
+            Ok(Expr::new_unary(self.new_id(), span, token, right))
         } else {
             self.call()
         }
@@ -341,7 +460,13 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
                 e = self.finish_call(e)?;
             } else if self.match_single(&TokenKind::Dot).is_some() {
                 let name = self.consume(&TokenKind::Identifier, "Expected property name after '.'.")?;
-                e = Expr::new_get(Box::new(e), name);
+                let span = e.span().to(name.span);
+                e = Expr::new_get(self.new_id(), span, Box::new(e), name);
+            } else if let Some(bracket) = self.match_single(&TokenKind::LeftBracket) {
+                let index = self.expression()?;
+                self.consume(&TokenKind::RightBracket, "Expected ']' after index.")?;
+                let span = e.span().to(self.previous);
+                e = Expr::new_index(self.new_id(), span, Box::new(e), bracket, Box::new(index));
             } else {
                 break
             }
@@ -368,36 +493,118 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
             // Another situation where jlox merely reports the error & rlox bubbles it up.
             Err(Error::syntactic(paren, "Function cannot have more than 255 arguments."))
         } else {
-            Ok(Expr::new_call(Box::new(callee), paren, args))
+            let span = callee.span().to(paren.span);
+            Ok(Expr::new_call(self.new_id(), span, Box::new(callee), paren, args))
         }
     }
 
     fn primary(&mut self) -> Result<Expr> {
-        let token = self.tokens.next().ok_or(Error::unexpected())?;
+        let token = self.advance().ok_or(Error::unexpected())?;
         let kind = token.kind.clone();
+        let span = token.span;
 
         match kind {
-            TokenKind::True => Ok(Expr::new_literal(true.into())),
-            TokenKind::False => Ok(Expr::new_literal(false.into())),
-            TokenKind::Nil => Ok(Expr::new_literal(value::Literal::Nil)),
-            TokenKind::Number(n) => Ok(Expr::new_literal(n.into())),
-            TokenKind::String(s) => Ok(Expr::new_literal(s.into())),
+            TokenKind::True => Ok(Expr::new_literal(self.new_id(), span, true.into())),
+            TokenKind::False => Ok(Expr::new_literal(self.new_id(), span, false.into())),
+            TokenKind::Nil => Ok(Expr::new_literal(self.new_id(), span, value::Literal::Nil)),
+            TokenKind::Number(n) => Ok(Expr::new_literal(self.new_id(), span, n.into())),
+            TokenKind::String(s) => Ok(Expr::new_literal(self.new_id(), span, s.into())),
+            TokenKind::Interpolation(s) => self.interpolation(token, s),
             TokenKind::Super => {
                 self.consume(&TokenKind::Dot, "Expected '.' following 'super'.")?;
                 let field = self.consume(&TokenKind::Identifier, "Expected superclass field name.")?;
-                Ok(Expr::new_super(token, field))
+                Ok(Expr::new_super(self.new_id(), span.to(field.span), token, field))
+            },
+                TokenKind::This => Ok(Expr::new_this(self.new_id(), span, token)),
+            TokenKind::Fun => {
+                self.consume(&TokenKind::LeftParen, "Expected '(' after 'fun'.")?;
+                let function = self.function_body(token, "function")?;
+                Ok(Expr::new_lambda(self.new_id(), function.span, Rc::new(function)))
             },
-                TokenKind::This => Ok(Expr::new_this(token)),
-            TokenKind::Identifier => Ok(Expr::new_variable(token)),
+            TokenKind::Identifier => Ok(Expr::new_variable(self.new_id(), span, token)),
             TokenKind::LeftParen => {
                 let expression = Box::new(self.expression()?);
                  self.consume(&TokenKind::RightParen, "Expected ')' after expression.")?;
-                 Ok(Expr::new_grouping(expression))
+                 Ok(Expr::new_grouping(self.new_id(), self.span_from(&token), expression))
+            },
+            TokenKind::LeftBracket => {
+                let mut elements = Vec::new();
+                if !self.check_next(&TokenKind::RightBracket) {
+                    elements.push(self.expression()?);
+                    while self.match_single(&TokenKind::Comma).is_some() {
+                        elements.push(self.expression()?);
+                    }
+                }
+                self.consume(&TokenKind::RightBracket, "Expected ']' after list elements.")?;
+                Ok(Expr::new_list(self.new_id(), self.span_from(&token), token, elements))
+            },
+            TokenKind::LeftBrace => {
+                let mut entries = Vec::new();
+                if !self.check_next(&TokenKind::RightBrace) {
+                    entries.push(self.map_entry()?);
+                    while self.match_single(&TokenKind::Comma).is_some() {
+                        entries.push(self.map_entry()?);
+                    }
+                }
+                self.consume(&TokenKind::RightBrace, "Expected '}' after map entries.")?;
+                Ok(Expr::new_map(self.new_id(), self.span_from(&token), token, entries))
             },
             _ => Err(Error::syntactic(token, ""))
         }
     }
 
+    /// Lowers an interpolated string to a chain of concatenations, each
+    /// interpolated expression being converted to a string first. `head` is
+    /// the part of the string before the first expression.
+    fn interpolation(&mut self, start: Token, head: String) -> Result<Expr> {
+        let plus = Token { kind: TokenKind::Plus, lexeme: "+".into(), doc: None, ..start.clone() };
+        let concat = |id, left: Expr, right: Expr| {
+            let span = left.span().to(*right.span());
+            Expr::new_binary(id, span, Box::new(left), plus.clone(), Box::new(right))
+        };
+
+        let mut string = Expr::new_literal(self.new_id(), start.span, head.into());
+        loop {
+            let expression = self.expression()?;
+            let span = *expression.span();
+            string = concat(self.new_id(), string, Expr::new_stringify(self.new_id(), span, Box::new(expression)));
+
+            let token = self.advance().ok_or(Error::unexpected())?;
+            match token.kind.clone() {
+                TokenKind::Interpolation(s) => {
+                    string = concat(self.new_id(), string, Expr::new_literal(self.new_id(), token.span, s.into()));
+                },
+                TokenKind::String(s) => {
+                    let string = concat(self.new_id(), string, Expr::new_literal(self.new_id(), token.span, s.into()));
+                    return Ok(string)
+                },
+                _ => return Err(Error::syntactic(token, "Expected '}' after interpolated expression.")),
+            }
+        }
+    }
+
+    fn map_entry(&mut self) -> Result<(Expr, Expr)> {
+        let key = self.expression()?;
+        self.consume(&TokenKind::Colon, "Expected ':' after map key.")?;
+        Ok((key, self.expression()?))
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.next()?;
+        self.previous = token.span;
+        Some(token)
+    }
+
+    /// The span from the start of `token` to the end of the last token consumed.
+    fn span_from(&self, token: &Token) -> Span {
+        token.span.to(self.previous)
+    }
+
     fn check_next(&mut self, kind: &TokenKind) -> bool {
         self.tokens.peek()
             .map(|t| &t.kind == kind)
@@ -408,7 +615,7 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
         if let Some(token) = self.match_single(kind) {
             Ok(token)
         } else {
-            Err(match self.tokens.next() {
+            Err(match self.advance() {
                 Some(t) => Error::syntactic(t, error_msg),
                 None => Error::unexpected(),
             })
@@ -424,7 +631,8 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
 
         while let Some(token) = self.match_any(kinds) {
             let right = Box::new(parse(self)?);
-            e = Expr::new_binary(Box::new(e), token, right)
+            let span = e.span().to(*right.span());
+            e = Expr::new_binary(self.new_id(), span, Box::new(e), token, right)
         }
 
         Ok(e)
@@ -432,7 +640,7 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
 
     fn match_single(&mut self, kind: &TokenKind) -> Option<Token> {
         if self.check_next(kind) {
-            self.tokens.next() 
+            self.advance()
         } else { 
             None 
         }
@@ -444,7 +652,7 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
 
     fn synchronise(& mut self) {
         loop {
-            let current = self.tokens.next();
+            let current = self.advance();
 
             if let Some(token) = current {
                 use TokenKind::*;
@@ -453,8 +661,8 @@ impl <T: Iterator<Item = Token>> Parser<Peekable<T>> {
                 if let Some(next) = self.tokens.peek() {
                     match next.kind {
                         Class | Fun | Var
-                        | For | If | While
-                        | Print | Return => break,
+                        | For | If | While | Break | Continue
+                        | Print | Return | Throw | Try => break,
                         _ => continue,
                     }
                 }
@@ -479,9 +687,9 @@ mod tests {
     fn string_literal_token() -> io::Result<()> {
         assert_tokens_parse_to_expr(
             vec![
-                Token { kind: TokenKind::String("abc".into()), lexeme: "".into(), line: 1 }, 
+                Token { kind: TokenKind::String("abc".into()), lexeme: "".into(), line: 1, column: 0, span: Span::default(), doc: None }, 
             ], 
-            Expr::new_literal(value::Literal::String("abc".into()))
+            Expr::new_literal(0, Span::default(), value::Literal::String("abc".into()))
         )
     }
 
@@ -489,9 +697,9 @@ mod tests {
     fn number_literal_token() -> io::Result<()> {
         assert_tokens_parse_to_expr(
             vec![
-                Token { kind: TokenKind::Number(HashableNumber(5.1)), lexeme: "".into(), line: 1 },
+                Token { kind: TokenKind::Number(HashableNumber(5.1)), lexeme: "".into(), line: 1, column: 0, span: Span::default(), doc: None },
             ], 
-            Expr::new_literal(value::Literal::Number(HashableNumber(5.1)))
+            Expr::new_literal(0, Span::default(), value::Literal::Number(HashableNumber(5.1)))
         )
     }
 
@@ -499,9 +707,9 @@ mod tests {
     fn nil_literal_token() -> io::Result<()> {
         assert_tokens_parse_to_expr(
             vec![
-                Token { kind: TokenKind::Nil, lexeme: "".into(), line: 1 }, 
+                Token { kind: TokenKind::Nil, lexeme: "".into(), line: 1, column: 0, span: Span::default(), doc: None }, 
             ], 
-            Expr::new_literal(value::Literal::Nil)
+            Expr::new_literal(0, Span::default(), value::Literal::Nil)
         )
     }
 
@@ -510,9 +718,9 @@ mod tests {
         for (kind, expected) in [(TokenKind::True, value::Literal::Bool(true)), (TokenKind::False, value::Literal::Bool(false))].iter() {
             assert_tokens_parse_to_expr(
                 vec![
-                    Token { kind: kind.clone(), lexeme: "".into(), line: 1 }, 
+                    Token { kind: kind.clone(), lexeme: "".into(), line: 1, column: 0, span: Span::default(), doc: None }, 
                 ], 
-                Expr::new_literal(expected.clone())
+                Expr::new_literal(0, Span::default(), expected.clone())
             )?;
         }
         Ok(())
@@ -526,40 +734,85 @@ mod tests {
                 not.clone(),
                 Token::make(TokenKind::True),
             ],
-            Expr::new_unary(not, Box::new(Expr::make(true)))
+            Expr::new_unary(0, Span::default(), not, Box::new(Expr::make(true)))
         )
     }
 
     #[test]
     fn set_tokens() -> io::Result<()> {
-        let hi = Token { kind: TokenKind::Identifier, lexeme: "hi".into(), line: 0 };
-        let name = Token { kind: TokenKind::Identifier, lexeme: "name".into(), line: 0 };
+        let hi = Token { kind: TokenKind::Identifier, lexeme: "hi".into(), line: 0, column: 0, span: Span::default(), doc: None };
+        let name = Token { kind: TokenKind::Identifier, lexeme: "name".into(), line: 0, column: 0, span: Span::default(), doc: None };
         assert_tokens_parse_to_expr(
             vec![
                 hi.clone(),
-                Token { kind: TokenKind::Dot, lexeme: ".".into(), line: 0 },
+                Token { kind: TokenKind::Dot, lexeme: ".".into(), line: 0, column: 0, span: Span::default(), doc: None },
                 name.clone(),
-                Token { kind: TokenKind::Equal, lexeme: "=".into(), line: 0 },
-                Token { kind: TokenKind::String("Hello".into()), lexeme: "\"Hello\"".into(), line: 0 },
-                Token { kind: TokenKind::Semicolon, lexeme: ";".into(), line: 0 },
+                Token { kind: TokenKind::Equal, lexeme: "=".into(), line: 0, column: 0, span: Span::default(), doc: None },
+                Token { kind: TokenKind::String("Hello".into()), lexeme: "\"Hello\"".into(), line: 0, column: 0, span: Span::default(), doc: None },
+                Token { kind: TokenKind::Semicolon, lexeme: ";".into(), line: 0, column: 0, span: Span::default(), doc: None },
             ],
             Expr::new_set(
-                Box::new(Expr::new_variable(hi)),
+                0,
+                Span::default(),
+                Box::new(Expr::new_variable(0, Span::default(), hi)),
                 name,
-                Box::new(Expr::new_literal(value::Literal::String("Hello".into())))
+                Box::new(Expr::new_literal(0, Span::default(), value::Literal::String("Hello".into())))
             )
         )
     }
 
+    #[test]
+    fn doc_comments_are_attached_to_declarations() {
+        let source = "
+            /// A class.
+            class A {
+                /// A method.
+                m() {}
+            }
+            /// Two
+            ///   lines.
+            fun f() {}
+            // Not a doc comment.
+            var v;
+        ";
+        let tokens = crate::scanner::Scanner::new(source).map(Result::unwrap);
+        let statements: Vec<_> = Parser::new(tokens)
+            .parse()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(
+            vec![Some("A class."), Some("Two\n  lines."), None],
+            statements.iter().map(Stmt::doc).collect::<Vec<_>>()
+        );
+        match &statements[0] {
+            Stmt::Class(c) => assert_eq!(Some("A method."), c.methods[0].doc.as_deref()),
+            _ => panic!("Expected a class."),
+        }
+    }
+
+    #[test]
+    fn ids_carry_on_from_the_first_id() {
+        let tokens = vec![Token::make(TokenKind::True), Token::make(TokenKind::Semicolon)];
+        let mut parser = Parser::with_first_id(tokens.into_iter(), 10);
+        let statements: Vec<_> = parser.parse().into_iter().map(Result::unwrap).collect();
+        match &statements[..] {
+            [Stmt::Expression(e)] => assert_eq!((10, 11), (e.expression.id(), e.id)),
+            _ => panic!("Expected an expression statement."),
+        }
+        assert_eq!(12, parser.next_id());
+    }
+
     impl Token {
         fn make(kind: TokenKind) -> Token {
-            Token { kind, lexeme: "".into(), line: 0 }
+            Token { kind, lexeme: "".into(), line: 0, column: 0, span: Span::default(), doc: None }
         }
     }
 
     impl Expr {
         fn make(b: bool) -> Expr {
-            Expr::new_literal(value::Literal::Bool(b))
+            Expr::new_literal(0, Span::default(), value::Literal::Bool(b))
         }
     }
 }
\ No newline at end of file
@@ -6,18 +6,49 @@ use std::{
 
 use crate::{
     interpreter::Interpreter, 
-    error::{Error, Result}, 
+    error::Error, 
     expr::{self, Expr}, 
     stmt::{self, Stmt}, 
     token::Token
 };
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum VariableState {
     Declared,
     Defined,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VariableKind {
+    Local,
+    Parameter,
+    // The exception named by a `catch` clause.
+    CatchBinding,
+    // `this` and `super`, which are never declared in the source.
+    Implicit,
+}
+
+#[derive(Debug)]
+struct Variable {
+    name: Option<Token>,
+    state: VariableState,
+    kind: VariableKind,
+    // How many functions deep the declaration is, so that we can tell
+    // when a variable is captured by a closure.
+    function_depth: usize,
+    captured: bool,
+    read: bool,
+    // The value of `Resolver::reads` when this was last read.
+    last_read: usize,
+    unread_assignment: Option<Token>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum FunctionType {
     None,
@@ -31,13 +62,24 @@ enum ClassType {
     None,
     Class,
     Subclass,
+    // A static method, which has no instance to refer to.
+    Static,
 }
 
 pub struct Resolver<W> {
     interpreter: Rc<RefCell<Interpreter<W>>>,
-    scopes: Vec<HashMap<String, VariableState>>,
+    scopes: Vec<HashMap<String, Variable>>,
     current_function: FunctionType,
     current_class: ClassType,
+    // How many loops enclose the code being resolved, within the current
+    // function.
+    loop_depth: usize,
+    function_depth: usize,
+    // A count of every variable read so far, used to find the reads which
+    // happened inside a loop.
+    reads: usize,
+    errors: Vec<Error>,
+    warnings: Vec<Error>,
 }
 
 impl <W> Resolver<W> {
@@ -47,124 +89,265 @@ impl <W> Resolver<W> {
             scopes: vec![HashMap::new()],
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            loop_depth: 0,
+            function_depth: 0,
+            reads: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
-    pub fn resolve_stmts(&mut self, s: &[Stmt]) -> Result<()> {
-        for stmt in s {
-            self.resolve_stmt(stmt)?;
+    /// Resolves every statement, returning all of the errors found along
+    /// the way rather than stopping at the first.
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Vec<Error> {
+        self.resolve_stmts(statements);
+        std::mem::take(&mut self.errors)
+    }
+
+    pub(crate) fn resolve_expression(&mut self, e: &Expr) -> Vec<Error> {
+        self.resolve_expr(e);
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Returns the warnings found since this was last called, in the order
+    /// they appear in the source. Globals never go out of scope, so only
+    /// local variables are ever warned about.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.sort_by_key(|w| w.token().map(|t| t.span.start));
+        warnings
+    }
+
+    fn resolve_stmts(&mut self, s: &[Stmt]) {
+        for (i, stmt) in s.iter().enumerate() {
+            self.resolve_stmt(stmt);
+
+            let jump = match stmt {
+                Stmt::Return(r) => &r.keyword,
+                Stmt::Break(b) => &b.keyword,
+                Stmt::Continue(c) => &c.keyword,
+                Stmt::Throw(t) => &t.keyword,
+                _ => continue,
+            };
+            if let Some(next) = s.get(i + 1) {
+                let unreachable = next.span().to(*s[s.len() - 1].span());
+                let message = format!("Code after {} is unreachable.", jump.lexeme);
+                self.warnings.push(Error::warning(jump.clone(), message).spanning(unreachable));
+            }
         }
-        Ok(())
     }
 
-    fn resolve_stmt(&mut self, s: &Stmt) -> Result<()> {
+    fn resolve_stmt(&mut self, s: &Stmt) {
         s.accept(self)
     }
 
-    fn resolve_expr(&mut self, e: &Expr) -> Result<()> {
+    fn resolve_expr(&mut self, e: &Expr) {
         e.accept(self)
     }
 
+    fn error<S: Into<String>>(&mut self, token: &Token, message: S) {
+        self.errors.push(Error::static_analyzer(token.clone(), message));
+    }
+
+    fn warning<S: Into<String>>(&mut self, token: &Token, message: S) {
+        self.warnings.push(Error::warning(token.clone(), message));
+    }
+
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+
+        for variable in scope.values() {
+            let name = match &variable.name {
+                Some(name) if !name.lexeme.starts_with('_') => name,
+                _ => continue,
+            };
+            if variable.kind == VariableKind::Local && !variable.read {
+                self.warning(name, format!("Local variable '{}' is never read.", name.lexeme));
+            } else if let (Some(assignment), false) = (&variable.unread_assignment, variable.captured) {
+                self.warning(assignment, format!("Value assigned to '{}' is never read.", name.lexeme));
+            }
+        }
+    }
+
+    fn declare(&mut self, n: &Token) {
+        self.declare_as(n, VariableKind::Local)
     }
 
-    fn declare(&mut self, n: &Token) -> Result<()> {
+    fn declare_as(&mut self, n: &Token, kind: VariableKind) {
+        let already_declared = self.scopes.last()
+            .map(|scope| scope.contains_key(&n.lexeme))
+            .unwrap_or(false);
+        if already_declared {
+            self.error(n, "A variable with this name already exists in this scope.");
+        }
+
+        let shadowed = self.scopes.iter()
+            .rev()
+            .skip(1)
+            .find_map(|scope| scope.get(&n.lexeme))
+            .and_then(|v| match v.kind {
+                VariableKind::Parameter => Some("a parameter"),
+                VariableKind::CatchBinding => Some("a caught exception"),
+                _ => None,
+            });
+        if let (VariableKind::Local, Some(shadowed)) = (kind, shadowed) {
+            self.warning(n, format!("Local variable '{}' shadows {}.", n.lexeme, shadowed));
+        }
+
+        let variable = Variable {
+            name: Some(n.clone()),
+            state: VariableState::Declared,
+            kind,
+            function_depth: self.function_depth,
+            captured: false,
+            read: false,
+            last_read: 0,
+            unread_assignment: None,
+        };
         if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&n.lexeme) {
-                return Err(Error::static_analyzer(
-                    n.clone(),
-                    "A variable with this name already exists in this scope."
-                ))
-            }
-            scope.insert(n.lexeme.clone(), VariableState::Declared);
+            scope.insert(n.lexeme.clone(), variable);
+        }
+    }
+
+    fn declare_implicit(&mut self, name: &str) {
+        let variable = Variable {
+            name: None,
+            state: VariableState::Defined,
+            kind: VariableKind::Implicit,
+            function_depth: self.function_depth,
+            captured: false,
+            read: false,
+            last_read: 0,
+            unread_assignment: None,
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.into(), variable);
         }
-        Ok(())
     }
 
     fn define(&mut self, n: &Token) {
         if let Some(scope) = self.scopes.last_mut() {
-            if let Some(value) = scope.get_mut(&n.lexeme) {
-                *value = VariableState::Defined;
+            if let Some(variable) = scope.get_mut(&n.lexeme) {
+                variable.state = VariableState::Defined;
             }
         }
     }
 
-    fn resolve_local(&mut self, e: &Expr, n: &Token) {
-        let index_and_scope = self.scopes.iter()
+    fn resolve_local(&mut self, id: usize, n: &Token, access: Access) {
+        self.reads += 1;
+        let (reads, function_depth) = (self.reads, self.function_depth);
+
+        let index_and_variable = self.scopes.iter_mut()
             .rev()
             .enumerate()
-            .find(|s| s.1.contains_key(&n.lexeme));
+            .find_map(|(i, s)| s.get_mut(&n.lexeme).map(|v| (i, v)));
+
+        if let Some((idx, variable)) = index_and_variable {
+            variable.captured |= variable.function_depth != function_depth;
+            match access {
+                Access::Read => {
+                    variable.read = true;
+                    variable.last_read = reads;
+                    variable.unread_assignment = None;
+                },
+                Access::Write => variable.unread_assignment = Some(n.clone()),
+            }
+            self.interpreter.borrow_mut().resolve(id, idx);
+        }
+    }
 
-        if let Some((idx, _)) = index_and_scope {
-            self.interpreter.borrow_mut().resolve(e, idx);
+    /// A value assigned at the end of a loop body can be read at the start
+    /// of the next iteration, so any variable read inside the loop counts
+    /// as having had its assignments read.
+    fn forgive_assignments_read_since(&mut self, reads: usize) {
+        for variable in self.scopes.iter_mut().flat_map(|s| s.values_mut()) {
+            if variable.last_read > reads {
+                variable.unread_assignment = None;
+            }
         }
     }
 
-    fn resolve_function(&mut self, f: &stmt::Function, t: FunctionType) -> Result<()> {
+    fn resolve_function(&mut self, f: &stmt::Function, t: FunctionType) {
         let enclosing_function = self.current_function;
+        let enclosing_loop_depth = self.loop_depth;
         self.current_function = t;
+        self.loop_depth = 0;
+        self.function_depth += 1;
 
         self.begin_scope();
         for param in f.params.iter() {
-            self.declare(&param)?;
-            self.define(&param);
+            self.declare_as(param, VariableKind::Parameter);
+            self.define(param);
         }
-        self.resolve_stmts(&f.body)?;
+        self.resolve_stmts(&f.body);
         self.end_scope();
+        self.function_depth -= 1;
         self.current_function = enclosing_function;
-        Ok(())
+        self.loop_depth = enclosing_loop_depth;
     }
 }
 
-impl <W> stmt::Visitor<Result<()>> for Resolver<W> {
-    fn visit_block_stmt(&mut self, b: &stmt::Block) -> Result<()> {
+impl <W> stmt::Visitor<()> for Resolver<W> {
+    fn visit_block_stmt(&mut self, b: &stmt::Block) {
         self.begin_scope();
-        self.resolve_stmts(&b.statements)?;
+        self.resolve_stmts(&b.statements);
         self.end_scope();
-        Ok(())
     }
 
-    fn visit_class_stmt(&mut self, c: &stmt::Class) -> Result<()> {
+    fn visit_break_stmt(&mut self, b: &stmt::Break) {
+        if self.loop_depth == 0 {
+            self.error(&b.keyword, "Can't use 'break' outside of a loop.");
+        }
+    }
+
+    fn visit_class_stmt(&mut self, c: &stmt::Class) {
         let enclosing_class = self.current_class;
         self.current_class = ClassType::Class;
 
-        self.declare(&c.name)?;
+        self.declare(&c.name);
         self.define(&c.name);
 
         if let Some(Expr::Variable(s)) = &c.superclass {
             if c.name.lexeme == s.name.lexeme {
-                return Err(Error::static_analyzer(
-                    s.name.clone(),
-                    "A class may not inheret from itself."
-                ))
+                self.error(&s.name, "A class may not inheret from itself.");
             }
         }
 
+        // Static methods are resolved outside of the scopes declaring `this`
+        // and `super`, as they're created outside of them at runtime.
+        let class_type = self.current_class;
+        self.current_class = ClassType::Static;
+        for method in c.static_methods.iter() {
+            self.resolve_function(method, FunctionType::Method);
+        }
+        self.current_class = class_type;
+
         if let Some(superclass) = &c.superclass {
             self.current_class = ClassType::Subclass;
-            self.resolve_expr(superclass)?;
+            self.resolve_expr(superclass);
 
             self.begin_scope();
-            self.scopes.last_mut().and_then(|s|
-                s.insert("super".into(), VariableState::Defined)
-            );
+            self.declare_implicit("super");
         }
 
         self.begin_scope();
-        self.scopes.last_mut().and_then(|s|
-            s.insert("this".into(), VariableState::Defined)
-        );
+        self.declare_implicit("this");
 
         for method in c.methods.iter() {
             let declaration = if method.name.lexeme == "init" {
                 FunctionType::Init
             } else { FunctionType::Method };
-            self.resolve_function(&method, declaration)?;
+            self.resolve_function(method, declaration);
+        }
+        for accessor in c.getters.iter().chain(c.setters.iter()) {
+            self.resolve_function(accessor, FunctionType::Method);
         }
 
         self.end_scope();
@@ -174,150 +357,352 @@ impl <W> stmt::Visitor<Result<()>> for Resolver<W> {
         }
 
         self.current_class = enclosing_class;
-        Ok(())
     }
 
-    fn visit_expression_stmt(&mut self, e: &stmt::Expression) -> Result<()> {
+    fn visit_continue_stmt(&mut self, c: &stmt::Continue) {
+        if self.loop_depth == 0 {
+            self.error(&c.keyword, "Can't use 'continue' outside of a loop.");
+        }
+    }
+
+    fn visit_expression_stmt(&mut self, e: &stmt::Expression) {
         self.resolve_expr(&e.expression)
     }
 
-    fn visit_function_stmt(&mut self, f: &stmt::Function) -> Result<()> {
-        self.declare(&f.name)?;
+    fn visit_function_stmt(&mut self, f: &Rc<stmt::Function>) {
+        self.declare(&f.name);
         self.define(&f.name);
-        self.resolve_function(&f, FunctionType::Function)?;
-        Ok(())
+        self.resolve_function(f, FunctionType::Function);
     }
 
-    fn visit_if_stmt(&mut self, i: &stmt::If) -> Result<()> {
-        self.resolve_expr(&i.condition)?;
-        self.resolve_stmt(&i.then_branch)?;
+    fn visit_if_stmt(&mut self, i: &stmt::If) {
+        self.resolve_expr(&i.condition);
+        self.resolve_stmt(&i.then_branch);
         if let Some(ref else_branch) = i.else_branch {
-            self.resolve_stmt(else_branch)?;
+            self.resolve_stmt(else_branch);
         }
-        Ok(())
     }
 
-    fn visit_print_stmt(&mut self, p: &stmt::Print) -> Result<()> {
+    fn visit_print_stmt(&mut self, p: &stmt::Print) {
         self.resolve_expr(&p.expression)
     }
 
-    fn visit_return_stmt(&mut self, r: &stmt::Return) -> Result<()> {
+    fn visit_return_stmt(&mut self, r: &stmt::Return) {
         match self.current_function {
-            FunctionType::None => Err(Error::static_analyzer(
-                r.keyword.clone(),
-                "Can't return from top-level code."
-            )),
-            FunctionType::Init if r.value.is_some() => Err(Error::static_analyzer(
-                r.keyword.clone(),
-                "Cannot return a value from an initialiser."
-            )),
-            _ => r.value.as_ref()
-                    .map(|v| self.resolve_expr(v))
-                    .unwrap_or(Ok(()))
-        }
-    }
-
-    fn visit_var_stmt(&mut self, v: &stmt::Var) -> Result<()> {
-        self.declare(&v.name)?;
+            FunctionType::None => self.error(&r.keyword, "Can't return from top-level code."),
+            FunctionType::Init if r.value.is_some() => {
+                self.error(&r.keyword, "Cannot return a value from an initialiser.")
+            },
+            _ => {},
+        }
+        if let Some(ref v) = r.value {
+            self.resolve_expr(v);
+        }
+    }
+
+    fn visit_throw_stmt(&mut self, t: &stmt::Throw) {
+        self.resolve_expr(&t.value)
+    }
+
+    fn visit_try_stmt(&mut self, t: &stmt::Try) {
+        self.begin_scope();
+        self.resolve_stmts(&t.body);
+        self.end_scope();
+
+        if let Some((name, body)) = &t.catch {
+            self.begin_scope();
+            // The exception doesn't have to be used.
+            self.declare_as(name, VariableKind::CatchBinding);
+            self.define(name);
+            self.resolve_stmts(body);
+            self.end_scope();
+        }
+
+        if let Some(body) = &t.finally {
+            self.begin_scope();
+            self.resolve_stmts(body);
+            self.end_scope();
+        }
+    }
+
+    fn visit_var_stmt(&mut self, v: &stmt::Var) {
+        self.declare(&v.name);
         if let Some(ref i) = v.initializer {
-            self.resolve_expr(i)?;
+            self.resolve_expr(i);
         }
         self.define(&v.name);
-        Ok(())
     }
 
-    fn visit_while_stmt(&mut self, w: &stmt::While) -> Result<()> {
-        self.resolve_expr(&w.condition)?;
-        self.resolve_stmt(&w.body)
+    fn visit_while_stmt(&mut self, w: &stmt::While) {
+        let reads = self.reads;
+        self.resolve_expr(&w.condition);
+        self.loop_depth += 1;
+        self.resolve_stmt(&w.body);
+        self.loop_depth -= 1;
+        if let Some(increment) = &w.increment {
+            self.resolve_expr(increment);
+        }
+        self.forgive_assignments_read_since(reads);
     }
 }
 
-impl <W> expr::Visitor<Result<()>> for Resolver<W> {
-    fn visit_assign_expr(&mut self, a: &expr::Assign) -> Result<()> {
-        self.resolve_expr(&a.value)?;
-        self.resolve_local(&Expr::Assign(a.clone()), &a.name);
-        Ok(())
+impl <W> expr::Visitor<()> for Resolver<W> {
+    fn visit_assign_expr(&mut self, a: &expr::Assign) {
+        self.resolve_expr(&a.value);
+        self.resolve_local(a.id, &a.name, Access::Write);
     }
 
-    fn visit_binary_expr(&mut self, e: &expr::Binary) -> Result<()> {
-        self.resolve_expr(&e.left)?;
+    fn visit_binary_expr(&mut self, e: &expr::Binary) {
+        self.resolve_expr(&e.left);
         self.resolve_expr(&e.right)
     }
 
-    fn visit_call_expr(&mut self, e: &expr::Call) -> Result<()> {
-        self.resolve_expr(&e.callee)?;
+    fn visit_call_expr(&mut self, e: &expr::Call) {
+        self.resolve_expr(&e.callee);
 
         for argument in &e.arguments {
-            self.resolve_expr(&argument)?;
+            self.resolve_expr(argument);
         }
-
-        Ok(())
     }
 
-    fn visit_get_expr(&mut self, g: &expr::Get) -> Result<()> {
+    fn visit_get_expr(&mut self, g: &expr::Get) {
         self.resolve_expr(&g.object)
     }
 
-    fn visit_grouping_expr(&mut self, e: &expr::Grouping) -> Result<()> {
+    fn visit_grouping_expr(&mut self, e: &expr::Grouping) {
         self.resolve_expr(&e.expression)
     }
 
-    fn visit_literal_expr(&mut self, _e: &expr::Literal) -> Result<()> {
-        Ok(())
+    fn visit_index_expr(&mut self, e: &expr::Index) {
+        self.resolve_expr(&e.object);
+        self.resolve_expr(&e.index)
     }
 
-    fn visit_logical_expr(&mut self, e: &expr::Logical) -> Result<()> {
-        self.resolve_expr(&e.left)?;
+    fn visit_lambda_expr(&mut self, e: &expr::Lambda) {
+        self.resolve_function(&e.function, FunctionType::Function)
+    }
+
+    fn visit_list_expr(&mut self, e: &expr::List) {
+        for element in e.elements.iter() {
+            self.resolve_expr(element);
+        }
+    }
+
+    fn visit_literal_expr(&mut self, _e: &expr::Literal) {}
+
+    fn visit_logical_expr(&mut self, e: &expr::Logical) {
+        self.resolve_expr(&e.left);
         self.resolve_expr(&e.right)
     }
 
-    fn visit_set_expr(&mut self, e: &expr::Set) -> Result<()> {
-        self.resolve_expr(&e.value)?;
+    fn visit_map_expr(&mut self, e: &expr::Map) {
+        for (key, value) in e.entries.iter() {
+            self.resolve_expr(key);
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_set_expr(&mut self, e: &expr::Set) {
+        self.resolve_expr(&e.value);
         self.resolve_expr(&e.object)
     }
 
-    fn visit_super_expr(&mut self, e: &expr::Super) -> Result<()> {
+    fn visit_set_index_expr(&mut self, e: &expr::SetIndex) {
+        self.resolve_expr(&e.object);
+        self.resolve_expr(&e.index);
+        self.resolve_expr(&e.value)
+    }
+
+    fn visit_stringify_expr(&mut self, e: &expr::Stringify) {
+        self.resolve_expr(&e.expression)
+    }
+
+    fn visit_super_expr(&mut self, e: &expr::Super) {
         match self.current_class {
-            ClassType::Subclass => Ok(self.resolve_local(&Expr::Super(e.clone()), &e.keyword)),
-            ClassType::None => Err(Error::static_analyzer(
-                e.keyword.clone(),
-                "Cannot use 'super' outside of a class."
-            )),
-            ClassType::Class => Err(Error::static_analyzer(
-                e.keyword.clone(),
-                "Cannot use 'super' in a class with no superclass."
-            )),
+            ClassType::Subclass => self.resolve_local(e.id, &e.keyword, Access::Read),
+            ClassType::None => self.error(&e.keyword, "Cannot use 'super' outside of a class."),
+            ClassType::Class => self.error(&e.keyword, "Cannot use 'super' in a class with no superclass."),
+            ClassType::Static => self.error(&e.keyword, "Can't use 'super' in a static method."),
         }
     }
 
-    fn visit_this_expr(&mut self, e: &expr::This) -> Result<()> {
+    fn visit_this_expr(&mut self, e: &expr::This) {
         match self.current_class {
-            ClassType::None => Err(Error::syntactic(
-                e.keyword.clone(),
-                "Can't use 'this' outside of a class."
-            )),
-            _ => Ok(self.resolve_local(&Expr::This(e.clone()), &e.keyword))
+            ClassType::None => self.error(&e.keyword, "Can't use 'this' outside of a class."),
+            ClassType::Static => self.error(&e.keyword, "Can't use 'this' in a static method."),
+            _ => self.resolve_local(e.id, &e.keyword, Access::Read),
         }
     }
 
-    fn visit_unary_expr(&mut self, e: &expr::Unary) -> Result<()> {
+    fn visit_unary_expr(&mut self, e: &expr::Unary) {
         self.resolve_expr(&e.right)
     }
 
-    fn visit_variable_expr(&mut self, e: &expr::Variable) -> Result<()> {
-        match self.scopes.last().and_then(|s| s.get(&e.name.lexeme)) {
-            Some(VariableState::Declared) => {
-                Err(Error::static_analyzer(
-                    e.name.clone(), 
-                    "Can't read local variable in its own initializer."
-                ))
-            },
-            _ => {
-                // jlox uses inheritance for AST nodes, but we have an enum so
-                // we need to reconstruct the Expr case to resolve the variable.
-                self.resolve_local(&Expr::Variable(e.clone()), &e.name);
-                Ok(())
-            }
+    fn visit_variable_expr(&mut self, e: &expr::Variable) {
+        let state = self.scopes.last().and_then(|s| s.get(&e.name.lexeme)).map(|v| &v.state);
+        if state == Some(&VariableState::Declared) {
+            self.error(&e.name, "Can't read local variable in its own initializer.");
         }
+        self.resolve_local(e.id, &e.name, Access::Read);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{natives::Capabilities, parser::Parser, scanner::Scanner};
+
+    fn resolve(source: &str) -> Vec<String> {
+        resolve_with_warnings(source).0
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        resolve_with_warnings(source).1
+    }
+
+    fn resolve_with_warnings(source: &str) -> (Vec<String>, Vec<String>) {
+        let tokens = Scanner::new(source).map(|t| t.unwrap());
+        let statements: Vec<_> = Parser::new(tokens).parse()
+            .into_iter()
+            .map(|s| s.unwrap())
+            .collect();
+        let interpreter = Rc::new(RefCell::new(Interpreter::new(Vec::new(), Capabilities::default())));
+        let mut resolver = Resolver::new(&interpreter);
+        let errors = resolver.resolve(&statements);
+        let to_strings = |errors: Vec<Error>| errors.iter().map(|e| e.to_string()).collect();
+        (to_strings(errors), to_strings(resolver.take_warnings()))
+    }
+
+    #[test]
+    fn valid_programs_have_no_errors() {
+        let errors = resolve("class A { init() { this.a = 1; } } class B < A { f() { return super.f; } }");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let errors = resolve("
+            return 1;
+            print this;
+            { var a = 1; var a = 2; }
+            { var b = b; }
+            class C < C {}
+            class D { f() { super.f(); } }
+        ");
+        assert_eq!(
+            vec![
+                "[line 2] Error at return: Can't return from top-level code.",
+                "[line 3] Error at this: Can't use 'this' outside of a class.",
+                "[line 4] Error at a: A variable with this name already exists in this scope.",
+                "[line 5] Error at b: Can't read local variable in its own initializer.",
+                "[line 6] Error at C: A class may not inheret from itself.",
+                "[line 7] Error at super: Cannot use 'super' in a class with no superclass.",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn keeps_going_after_errors_inside_functions() {
+        let errors = resolve("
+            fun f(a, a) { return; }
+            fun g() { var c; var c; }
+        ");
+        assert_eq!(2, errors.len());
+    }
+
+    #[test]
+    fn valid_programs_have_no_warnings() {
+        let warnings = warnings("
+            var unused_global = 1;
+            fun counter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            fun sum(n) {
+                var total = 0;
+                var i = 0;
+                while (i < n) { total = total + i; i = i + 1; }
+                return total;
+            }
+            { var _ignored = 1; }
+        ");
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn warns_about_unused_locals() {
+        let warnings = warnings("fun f(a) { var b = a; }");
+        assert_eq!(vec!["[line 1] Warning at b: Local variable 'b' is never read."], warnings);
+    }
+
+    #[test]
+    fn warns_about_unreachable_code() {
+        let warnings = warnings("fun f() {\n return 1;\n print 2;\n print 3;\n}");
+        assert_eq!(vec!["[line 2] Warning at return: Code after return is unreachable."], warnings);
+    }
+
+    #[test]
+    fn warns_about_locals_shadowing_parameters() {
+        let warnings = warnings("fun f(a) { { var a = 1; print a; } }");
+        assert_eq!(vec!["[line 1] Warning at a: Local variable 'a' shadows a parameter."], warnings);
+    }
+
+    #[test]
+    fn catch_bindings_are_not_parameters() {
+        let warnings = warnings("
+            try {} catch (e) { { var e = 1; print e; } }
+            fun f() { try {} catch (unused) {} }
+        ");
+        assert_eq!(vec!["[line 2] Warning at e: Local variable 'e' shadows a caught exception."], warnings);
+    }
+
+    #[test]
+    fn warns_about_assignments_which_are_never_read() {
+        let warnings = warnings("fun f() {\n var a = 1;\n print a;\n a = 2;\n}");
+        assert_eq!(vec!["[line 4] Warning at a: Value assigned to 'a' is never read."], warnings);
+    }
+
+    #[test]
+    fn warnings_are_not_errors() {
+        assert!(resolve("fun f() { var a; return; print 1; }").is_empty());
+    }
+
+    #[test]
+    fn break_and_continue_must_be_inside_a_loop() {
+        let errors = resolve("
+            break;
+            while (true) { fun f() { continue; } }
+            while (true) { { break; } continue; }
+        ");
+        assert_eq!(
+            vec![
+                "[line 2] Error at break: Can't use 'break' outside of a loop.",
+                "[line 3] Error at continue: Can't use 'continue' outside of a loop.",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn static_methods_have_no_this_or_super() {
+        let errors = resolve("
+            class A {
+                class make() { return this; }
+                size { return this.n; }
+                size=(n) { this.n = n; }
+            }
+            class B < A {
+                class make() { return fun () { return super.make(); }; }
+            }
+        ");
+        assert_eq!(
+            vec![
+                "[line 3] Error at this: Can't use 'this' in a static method.",
+                "[line 8] Error at super: Can't use 'super' in a static method.",
+            ],
+            errors
+        );
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use crate::{
     error::{Error, Result},
-    token::{HashableNumber, Token, TokenKind},
+    token::{HashableNumber, Span, Token, TokenKind},
 };
 use peekmore::{PeekMore, PeekMoreIterator};
 use phf::phf_map;
@@ -8,9 +8,13 @@ use std::str::Chars;
 
 static KEYWORDS: phf::Map<&'static str, TokenKind> = phf_map! {
     "and" => TokenKind::And,
+    "break" => TokenKind::Break,
+    "catch" => TokenKind::Catch,
     "class" => TokenKind::Class,
+    "continue" => TokenKind::Continue,
     "else" => TokenKind::Else,
     "false" => TokenKind::False,
+    "finally" => TokenKind::Finally,
     "for" => TokenKind::For,
     "fun" => TokenKind::Fun,
     "if" => TokenKind::If,
@@ -20,7 +24,9 @@ static KEYWORDS: phf::Map<&'static str, TokenKind> = phf_map! {
     "return" => TokenKind::Return,
     "super" => TokenKind::Super,
     "this" => TokenKind::This,
+    "throw" => TokenKind::Throw,
     "true" => TokenKind::True,
+    "try" => TokenKind::Try,
     "var" => TokenKind::Var,
     "while" => TokenKind::While,
 };
@@ -35,12 +41,25 @@ pub struct Scanner<'a> {
     src: PeekMoreIterator<Chars<'a>>,
     lexeme_buffer: String,
     line: usize,
+    // Byte offset and column of the next character.
+    offset: usize,
+    column: usize,
+    // Byte offset and column of the first character of the current lexeme.
+    lexeme_start: usize,
+    lexeme_column: usize,
+    // One entry for each interpolated expression being scanned, counting
+    // the braces opened inside it so far.
+    interpolations: Vec<usize>,
+    // Doc comments waiting to be attached to the next token.
+    doc: Option<String>,
 }
 
 impl <'a> Iterator for Scanner<'a> {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Result<Token>> {
+        self.lexeme_start = self.offset;
+        self.lexeme_column = self.column;
         let next = self.next_token_kind();
 
         let lexeme = self.lexeme_buffer.clone();
@@ -53,6 +72,9 @@ impl <'a> Iterator for Scanner<'a> {
                         kind,
                         lexeme,
                         line: self.line,
+                        column: self.lexeme_column,
+                        span: self.lexeme_span(),
+                        doc: self.doc.take(),
                     }
                 ))
             },
@@ -62,51 +84,97 @@ impl <'a> Iterator for Scanner<'a> {
     }
 }
 
+/// The value of `text` if the whole of it is a single number literal, with
+/// nothing before or after it.
+pub(crate) fn number_literal(text: &str) -> Option<f64> {
+    match Scanner::new(text).next() {
+        Some(Ok(Token { kind: TokenKind::Number(n), span, .. })) if span == Span::new(0, text.len()) => Some(n.value()),
+        _ => None,
+    }
+}
+
 impl <'a> Scanner<'a> {
     pub fn new(src: &'a str) -> Self {
         Self {
             src: src.chars().peekmore(),
             lexeme_buffer: String::new(),
             line: 1,
+            offset: 0,
+            column: 1,
+            lexeme_start: 0,
+            lexeme_column: 1,
+            interpolations: Vec::new(),
+            doc: None,
         }
     }
 
-    pub fn scan_tokens(self) -> Vec<Result<Token>> {
-        let line = self.line;
-        let mut tokens = self.collect::<Vec<Result<Token>>>();
+    pub fn scan_tokens(mut self) -> Vec<Result<Token>> {
+        let mut tokens = self.by_ref().collect::<Vec<Result<Token>>>();
         tokens.push(Ok(Token {
             kind: TokenKind::EndOfFile,
             lexeme: "".to_string(),
-            line: line,
+            line: self.line,
+            column: self.column,
+            span: Span::new(self.offset, self.offset),
+            doc: self.doc.take(),
         }));
         tokens
     }
 
     fn next_token_kind(&mut self) -> ScannerResult {
-        match self.src.next() {
+        match self.advance() {
             None => ScannerResult::NoMoreTokens,
             Some(next_char) => {
-                self.lexeme_buffer.push(next_char);
                 use TokenKind::*;
                 match next_char {
                     '(' => ScannerResult::Next(Ok(LeftParen)),
                     ')' => ScannerResult::Next(Ok(RightParen)),
-                    '{' => ScannerResult::Next(Ok(LeftBrace)),
-                    '}' => ScannerResult::Next(Ok(RightBrace)),
+                    '{' => {
+                        if let Some(depth) = self.interpolations.last_mut() {
+                            *depth += 1;
+                        }
+                        ScannerResult::Next(Ok(LeftBrace))
+                    },
+                    '}' => match self.interpolations.last_mut() {
+                        // This closes an interpolated expression, so the
+                        // rest of the string it was part of follows.
+                        Some(0) => {
+                            self.interpolations.pop();
+                            ScannerResult::Next(self.extract_string())
+                        },
+                        Some(depth) => {
+                            *depth -= 1;
+                            ScannerResult::Next(Ok(RightBrace))
+                        },
+                        None => ScannerResult::Next(Ok(RightBrace)),
+                    },
+                    '[' => ScannerResult::Next(Ok(LeftBracket)),
+                    ']' => ScannerResult::Next(Ok(RightBracket)),
+                    ':' => ScannerResult::Next(Ok(Colon)),
                     ',' => ScannerResult::Next(Ok(Comma)),
                     '.' => ScannerResult::Next(Ok(Dot)),
                     '-' => ScannerResult::Next(Ok(Minus)),
                     '+' => ScannerResult::Next(Ok(Plus)),
                     ';' => ScannerResult::Next(Ok(Semicolon)),
                     '*' => ScannerResult::Next(Ok(Star)),
+                    '%' => ScannerResult::Next(Ok(Percent)),
                     '!' => ScannerResult::Next(Ok(if self.does_next_match('=') { BangEqual } else { Bang })),
                     '=' => ScannerResult::Next(Ok(if self.does_next_match('=') { EqualEqual } else { Equal })),
                     '<' => ScannerResult::Next(Ok(if self.does_next_match('=') { LessEqual } else { Less })),
                     '>' => ScannerResult::Next(Ok(if self.does_next_match('=') { GreaterEqual } else { Greater })),
                     '/' => {
                         if self.does_next_match('/') { // is this a comment?
-                            self.advance_until_match('\n');
+                            if self.does_next_match('/') {
+                                self.extract_doc_comment();
+                            } else {
+                                self.advance_until_match('\n');
+                            }
                             ScannerResult::Skip
+                        } else if self.does_next_match('*') {
+                            match self.skip_block_comment() {
+                                Ok(()) => ScannerResult::Skip,
+                                Err(e) => ScannerResult::Next(Err(e)),
+                            }
                         } else {
                             ScannerResult::Next(Ok(Slash))
                         }
@@ -117,56 +185,209 @@ impl <'a> Scanner<'a> {
                         ScannerResult::Skip
                     },
                     '"' => ScannerResult::Next(self.extract_string()),
-                    c if c.is_digit(10) => ScannerResult::Next(self.extract_number()),
+                    c if c.is_ascii_digit() => ScannerResult::Next(self.extract_number(c)),
                     c if can_start_identifier(&c) => ScannerResult::Next(self.extract_identifier()),
-                    c => ScannerResult::Next(Err(Error::lexical(self.line, format!("Unexpected character '{}'", c)))),
+                    c => ScannerResult::Next(Err(self.error(format!("Unexpected character '{}'", c)))),
                 }
             }
         }
     }
 
+    fn advance(&mut self) -> Option<char> {
+        let next = self.src.next()?;
+        self.lexeme_buffer.push(next);
+        self.offset += next.len_utf8();
+        if next == '\n' {
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(next)
+    }
+
+    fn lexeme_span(&self) -> Span {
+        Span::new(self.lexeme_start, self.offset)
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> Error {
+        Error::lexical(self.line, self.lexeme_span(), message)
+    }
+
     fn does_next_match(&mut self, c: char) -> bool {
         match self.src.peek() {
             Some(next) if c == *next => {
-                self.lexeme_buffer.push(self.src.next().unwrap());
+                self.advance();
                 true
             }
             _ => false,
         }
     }
 
+    /// Scans the rest of a `///` comment, adding its text to the doc comment
+    /// for the next token.
+    fn extract_doc_comment(&mut self) {
+        let mut text = String::new();
+        self.advance_until_for_each(|c| c == &'\n', |c| text.push(c));
+        let line = text.strip_prefix(' ').unwrap_or(&text).trim_end();
+
+        match &mut self.doc {
+            Some(doc) => {
+                doc.push('\n');
+                doc.push_str(line);
+            },
+            None => self.doc = Some(line.to_string()),
+        }
+    }
+
+    /// Skips a block comment, the opening `/*` having already been consumed.
+    /// Block comments nest, so every `/*` inside one needs its own `*/`.
+    fn skip_block_comment(&mut self) -> Result<()> {
+        let start_line = self.line;
+        let opening = Span::new(self.lexeme_start, self.lexeme_start + 2);
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                None => return Err(Error::lexical(start_line, opening, "Unterminated block comment.")),
+                Some('/') if self.does_next_match('*') => depth += 1,
+                Some('*') if self.does_next_match('/') => depth -= 1,
+                Some('\n') => self.line += 1,
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans the rest of a string literal, up to either its closing quote
+    /// or the start of an interpolated expression. The first bad escape is
+    /// reported once the whole string has been consumed, so that scanning
+    /// can carry on from a sensible place.
     fn extract_string(&mut self) -> Result<TokenKind> {
-        let mut newline_count = 0;
-        self.advance_until_match_for_each('"', |c| if c == '\n' { newline_count += 1 });
-        self.line += newline_count;
-        match self.src.next() {
-            None => Err(Error::lexical(self.line, "Unterminated string literal.")),
-            Some(q) => { // q here must be " due to iterate_until
-                self.lexeme_buffer.push(q);
-                Ok(TokenKind::String(self.lexeme_buffer.trim_matches('"').to_string()))
+        let mut value = String::new();
+        let mut error = None;
+        loop {
+            match self.advance() {
+                None => return Err(self.error("Unterminated string literal.")),
+                Some('"') => break,
+                Some('\n') => {
+                    self.line += 1;
+                    value.push('\n');
+                },
+                Some('\\') => match self.extract_escape() {
+                    Ok(c) => value.push(c),
+                    Err(e) => { error.get_or_insert(e); },
+                },
+                Some('$') if self.does_next_match('{') => {
+                    self.interpolations.push(0);
+                    return error.map_or(Ok(TokenKind::Interpolation(value)), Err)
+                },
+                Some(c) => value.push(c),
+            }
+        }
+        error.map_or(Ok(TokenKind::String(value)), Err)
+    }
+
+    /// Scans the rest of an escape sequence, the backslash having already
+    /// been consumed.
+    fn extract_escape(&mut self) -> Result<char> {
+        let start = self.offset - 1;
+        let error = |scanner: &Self, message: String| {
+            Error::lexical(scanner.line, Span::new(start, scanner.offset), message)
+        };
+
+        match self.advance() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('$') => Ok('$'),
+            Some('u') => {
+                if !self.does_next_match('{') {
+                    return Err(error(self, "Expected '{' after '\\u'.".to_string()))
+                }
+                let mut digits = String::new();
+                self.advance_until_for_each(|c| !c.is_ascii_hexdigit(), |c| digits.push(c));
+                if !self.does_next_match('}') {
+                    return Err(error(self, "Expected '}' after unicode escape.".to_string()))
+                }
+                u32::from_str_radix(&digits, 16).ok()
+                    .filter(|_| (1..=6).contains(&digits.len()))
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error(self, format!("'{}' is not a valid unicode scalar value.", digits)))
+            },
+            Some(c) => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                Err(error(self, format!("Invalid escape sequence '\\{}'.", c.escape_default())))
             },
+            None => Err(self.error("Unterminated string literal.")),
         }
     }
 
-    fn extract_number(&mut self) -> Result<TokenKind> {
-        self.advance_until(|n| !n.is_digit(10));
+    fn extract_number(&mut self, first: char) -> Result<TokenKind> {
+        let radix = match (first, self.src.peek_nth(0)) {
+            ('0', Some('x')) | ('0', Some('X')) => 16,
+            ('0', Some('b')) | ('0', Some('B')) => 2,
+            _ => 10,
+        };
+
+        let number = if radix == 10 {
+            let mut text = self.extract_digits(first.to_string(), 10)?;
 
-        if let Some(&'.') = self.src.peek() {
-            if let Some(maybe_digit) = self.src.peek_next() {
-                if maybe_digit.is_digit(10) {
-                    self.lexeme_buffer.push(self.src.next().unwrap());
-                    self.advance_until(|n| !n.is_digit(10));
+            if self.src.peek_nth(0) == Some(&'.') && self.next_is_digit(1) {
+                self.advance();
+                text.push('.');
+                text += &self.extract_digits(String::new(), 10)?;
+            }
+
+            if matches!(self.src.peek_nth(0), Some('e') | Some('E')) {
+                self.advance();
+                text.push('e');
+                if let Some(sign) = self.src.peek().copied().filter(|c| matches!(c, '+' | '-')) {
+                    self.advance();
+                    text.push(sign);
                 }
+                let exponent = self.extract_digits(String::new(), 10)?;
+                if exponent.is_empty() {
+                    return Err(self.error("Expected digits in exponent."))
+                }
+                text += &exponent;
             }
-        }
 
-        match self.lexeme_buffer.parse() {
-            Err(_) => Err(Error::lexical(
-                self.line,
+            text.parse().ok()
+        } else {
+            self.advance();
+            let digits = self.extract_digits(String::new(), radix)?;
+            if digits.is_empty() {
+                return Err(self.error(format!("Expected digits after '{}'.", self.lexeme_buffer)))
+            }
+            // Accumulating in a float rather than an integer means literals
+            // too big for a u64 lose precision instead of failing to scan,
+            // just like decimal ones.
+            let digits = digits.chars().filter_map(|c| c.to_digit(radix));
+            Some(digits.fold(0.0, |n, digit| n * f64::from(radix) + f64::from(digit)))
+        };
+
+        match number {
+            None => Err(self.error(
                 format!("Could not convert {} into a number", self.lexeme_buffer.clone())
             )),
-            Ok(number) => Ok(TokenKind::Number(HashableNumber(number))),
+            Some(number) => Ok(TokenKind::Number(HashableNumber(number))),
+        }
+    }
+
+    fn next_is_digit(&mut self, n: usize) -> bool {
+        self.src.peek_nth(n).is_some_and(char::is_ascii_digit)
+    }
+
+    /// Scans a run of digits in `radix` onto the end of `digits`, returning
+    /// them without any `_` separators.
+    fn extract_digits(&mut self, mut digits: String, radix: u32) -> Result<String> {
+        self.advance_until_for_each(|c| !c.is_digit(radix) && c != &'_', |c| digits.push(c));
+        if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+            return Err(self.error("Digit separators must be between digits."))
         }
+        Ok(digits.replace('_', ""))
     }
 
     fn extract_identifier(&mut self) -> Result<TokenKind> {
@@ -187,32 +408,82 @@ impl <'a> Scanner<'a> {
         self.advance_until_for_each(should_stop, |_| {})
     }
 
-    fn advance_until_match_for_each(
-        &mut self,
-        c: char,
-        f: impl FnMut(char) -> ()
-    ) {
-        self.advance_until_for_each(|n| n == &c, f);
-    }
-
     fn advance_until_for_each(
         &mut self,
         should_stop: impl Fn(&char) -> bool,
-        mut f: impl FnMut(char) -> ()
+        mut f: impl FnMut(char)
     ) {
         let is_done = |nxt: Option<&char>| nxt.is_none() || should_stop(nxt.unwrap());
         while !is_done(self.src.peek()) {
-            let next = self.src.next().unwrap();
-            self.lexeme_buffer.push(next);
+            let next = self.advance().unwrap();
             f(next);
         }
     }
 }
 
 fn can_start_identifier(c: &char) -> bool {
-    c.is_ascii_alphabetic() || c == &'_'
+    unicode_ident::is_xid_start(*c) || c == &'_'
 }
 
 fn is_part_of_valid_identifier(c: &char) -> bool {
-    can_start_identifier(c) || c.is_digit(10)
+    unicode_ident::is_xid_continue(*c)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Lox;
+
+    use super::*;
+
+    /// Runs `source` in a fresh interpreter, returning what it printed.
+    fn run(source: &str) -> String {
+        let mut out = Vec::new();
+        Lox::new(&mut out).run_source(source).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn scan_errors(source: &str) -> Vec<String> {
+        Scanner::new(source).filter_map(Result::err).map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn strings_can_contain_escapes_and_interpolations() {
+        assert_eq!("\"Hi\"\tLox\\!\n2 [nil, Lox] ${}\n", run(r#"
+            var name = "Lox";
+            print "\"Hi\"\t${name}\\\u{21}";
+            print "${1 + 1} ${ {"k": [nil, "${name}"]}["k"] } \${}";
+        "#));
+    }
+
+    #[test]
+    fn bad_escapes_are_lexical_errors() {
+        assert_eq!(
+            vec!["[line 2] Error: Invalid escape sequence '\\q'."],
+            scan_errors("print \"ok\";\nprint \"a \\q b\";")
+        );
+    }
+
+    #[test]
+    fn block_comments_nest_and_span_lines() {
+        assert_eq!("1\n2\n", run("/* outer /* inner\n*/ still a comment */ print 1;\nprint 2;"));
+        assert_eq!(vec!["[line 1] Error: Unterminated block comment."], scan_errors("/* one\n/* two */\n"));
+    }
+
+    #[test]
+    fn numbers_can_be_written_in_other_bases_and_with_exponents() {
+        assert_eq!(Some(255.0), number_literal("0xFF"));
+        assert_eq!(Some(10.0), number_literal("0b1010"));
+        assert_eq!(Some(1e-9), number_literal("1e-9"));
+        assert_eq!(Some(2500.0), number_literal("2.5E+3"));
+        assert_eq!(Some(1_000_000.5), number_literal("1_000_000.5"));
+        assert_eq!(Some(2f64.powi(64)), number_literal("0x1_0000_0000_0000_0000"));
+        assert_eq!(vec!["[line 1] Error: Digit separators must be between digits."], scan_errors("1__0"));
+        assert_eq!(vec!["[line 1] Error: Expected digits in exponent."], scan_errors("1e"));
+        assert_eq!(vec!["[line 1] Error: Expected digits in exponent."], scan_errors("1e+"));
+    }
+
+    #[test]
+    fn identifiers_can_use_unicode_letters() {
+        assert_eq!("6\n", run("var café = 1; var 名前 = 2; var _x1 = 3; print café + 名前 + _x1;"));
+    }
 }
\ No newline at end of file
@@ -1,20 +1,107 @@
+use std::rc::Rc;
+
 use astgen::generate_ast;
 use crate::{
     expr::Expr,
-    token::Token,
+    token::{Span, Token},
 };
 
 generate_ast!(
     Stmt,
+    { span: Span },
     [
-        Block      => { statements: Vec<Stmt> };
-        Class      => { name: Token, superclass: Option<Expr>, methods: Vec<Function> };
+        Block      => { brace: Token, statements: Vec<Stmt> };
+        Break      => { keyword: Token };
+        Class      => {
+            name: Token,
+            superclass: Option<Expr>,
+            methods: Vec<Rc<Function>>,
+            getters: Vec<Rc<Function>>,
+            setters: Vec<Rc<Function>>,
+            static_methods: Vec<Rc<Function>>,
+            doc: Option<String>
+        };
+        Continue   => { keyword: Token };
         Expression => { expression: Expr };
-        Function   => { name: Token, params: Vec<Token>, body: Vec<Stmt> };
+        Function   => Rc { name: Token, params: Vec<Token>, body: Vec<Stmt>, doc: Option<String> };
         If         => { condition: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>> };
         Print      => { expression: Expr };
         Return     => { keyword: Token, value: Option<Expr> };
-        Var        => { name: Token, initializer: Option<Expr> };
-        While      => { condition: Expr, body: Box<Stmt> };
+        Throw      => { keyword: Token, value: Expr };
+        // At least one of `catch` and `finally` is always set. `catch` holds
+        // the name the exception is bound to as well as its body.
+        Try        => { keyword: Token, body: Vec<Stmt>, catch: Option<(Token, Vec<Stmt>)>, finally: Option<Vec<Stmt>> };
+        Var        => { name: Token, initializer: Option<Expr>, doc: Option<String> };
+        // `increment` is only set for desugared `for` loops. It runs after
+        // the body, even when the body continues.
+        While      => { condition: Expr, body: Box<Stmt>, increment: Option<Expr> };
     ]
-);
\ No newline at end of file
+);
+
+impl Stmt {
+    /// The doc comment written before this declaration, if it is one.
+    ///
+    /// ```
+    /// use rlox_treewalk::{parser::Parser, scanner::Scanner, stmt::Stmt};
+    ///
+    /// let source = "/// A shape.\nclass Shape {\n  /// Its area.\n  area { return 0; }\n}";
+    /// let tokens = Scanner::new(source).map(Result::unwrap);
+    /// let statements: Vec<_> = Parser::new(tokens).parse().into_iter().map(Result::unwrap).collect();
+    /// assert_eq!(Some("A shape."), statements[0].doc());
+    /// match &statements[0] {
+    ///     Stmt::Class(class) => {
+    ///         let area = class.members().next().unwrap();
+    ///         assert_eq!(("area", Some("Its area.")), (area.name(), area.doc()));
+    ///     },
+    ///     _ => panic!("Expected a class."),
+    /// }
+    /// ```
+    pub fn doc(&self) -> Option<&str> {
+        match self {
+            Stmt::Class(c) => c.doc(),
+            Stmt::Function(f) => f.doc(),
+            Stmt::Var(v) => v.doc(),
+            _ => None,
+        }
+    }
+}
+
+impl Class {
+    pub fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    /// Every method the class declares, including its getters, setters and
+    /// static methods.
+    pub fn members(&self) -> impl Iterator<Item = &Function> {
+        self.methods.iter()
+            .chain(&self.getters)
+            .chain(&self.setters)
+            .chain(&self.static_methods)
+            .map(Rc::as_ref)
+    }
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+}
+
+impl Var {
+    pub fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+}
\ No newline at end of file
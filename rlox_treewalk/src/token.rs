@@ -5,12 +5,63 @@ pub struct Token {
     pub(crate) kind: TokenKind,
     pub(crate) lexeme: String,
     pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) span: Span,
+    // The text of any `///` comments directly before this token.
+    pub(crate) doc: Option<String>,
+}
+
+impl Token {
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+}
+
+/// A range of byte offsets into the source a token or node was parsed from.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span which covers both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum TokenKind {
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Colon, Comma, Dot, Minus, Percent, Plus, Semicolon, Slash, Star,
 
     Bang, BangEqual,
     Equal, EqualEqual,
@@ -18,9 +69,11 @@ pub enum TokenKind {
     Less, LessEqual,
 
     Identifier, String(String), Number(HashableNumber),
+    // The part of a string literal before an interpolated expression.
+    Interpolation(String),
 
-    And, Class, Else, False, Fun, For, If, Nil, Or,
-    Print, Return, Super, This, True, Var, While,
+    And, Break, Catch, Class, Continue, Else, False, Finally, Fun, For, If, Nil, Or,
+    Print, Return, Super, This, Throw, True, Try, Var, While,
 
     EndOfFile,
 }
@@ -36,4 +89,10 @@ impl Hash for HashableNumber {
     }
 }
 
+impl HashableNumber {
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+}
+
 impl Eq for HashableNumber {}
\ No newline at end of file
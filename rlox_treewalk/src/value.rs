@@ -1,15 +1,19 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    fmt::{self, Debug, Display},
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    mem,
     rc::Rc
 };
 
 use crate::{
+    callable::Arity,
     environment::Environment,
     error::{Error, Result},
+    gc::{self, Heap, OutOfMemory, Trace},
+    methods::BuiltinMethod,
     stmt,
-    token::{Token, HashableNumber},
+    token::{HashableNumber, Token, TokenKind},
 };
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -20,6 +24,15 @@ pub(crate) enum Literal {
     String(String),
 }
 
+impl Literal {
+    fn size(&self) -> usize {
+        mem::size_of::<Literal>() + match self {
+            Literal::String(s) => s.capacity(),
+            _ => 0,
+        }
+    }
+}
+
 impl From<bool> for Literal {
     fn from(b: bool) -> Self {
         Literal::Bool(b)
@@ -39,12 +52,15 @@ impl From<String> for Literal {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) enum Value {
+pub enum Value {
     Bool(bool),
+    BuiltinMethod(BuiltinMethod),
     Class(ClassPointer),
     Function(Function),
     Instance(InstancePointer),
-    NativeFn(NativeFn<&'static dyn Fn() -> Value>),
+    List(ListPointer),
+    Map(MapPointer),
+    NativeFn(NativeFn),
     Nil,
     Number(f64),
     String(String),
@@ -73,23 +89,49 @@ impl From<bool> for Value {
     }
 }
 
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
 impl Value {
-    pub(crate) fn new_class<S: Into<String>>(name: S, superclass: Option<ClassPointer>, fields: HashMap<String, Value>) -> Self {
-        Value::Class(ClassPointer::new(name.into(), superclass, fields))
+    pub(crate) fn new_class<S: Into<String>>(
+        name: S,
+        superclass: Option<ClassPointer>,
+        members: ClassMembers,
+        heap: &mut Heap,
+    ) -> std::result::Result<Self, OutOfMemory> {
+        ClassPointer::new(name.into(), superclass, members, heap).map(Value::Class)
     }
 
-    pub(crate) fn new_native_fn(body: &'static dyn Fn() -> Value) -> Self {
-        Value::NativeFn(NativeFn { body })
+    pub(crate) fn new_native_fn<S: Into<String>>(name: S, arity: Arity, interpreter: usize, id: usize) -> Self {
+        Value::NativeFn(NativeFn { name: name.into(), arity, interpreter, id })
     }
 
     pub(crate) fn new_function(
-        declaration: stmt::Function,
+        declaration: Rc<stmt::Function>,
         closure: Rc<RefCell<Environment>>,
         is_init: bool
     ) -> Self {
         Value::Function(Function::new(declaration, closure, is_init))
     }
 
+    /// Roughly how many bytes the value takes up wherever it's stored. The
+    /// heap accounts for the objects it refers to separately.
+    pub(crate) fn size(&self) -> usize {
+        mem::size_of::<Value>() + match self {
+            Value::String(s) => s.capacity(),
+            _ => 0,
+        }
+    }
+
     pub(crate) fn is_equal(&self, other: &Value) -> bool {
         use Value::*;
         match (self, other) {
@@ -105,15 +147,27 @@ impl Value {
                 }
             },
             (String(s), String(o)) => s == o,
+            (List(s), List(o)) => Rc::ptr_eq(&s.0, &o.0),
+            (Map(s), Map(o)) => Rc::ptr_eq(&s.0, &o.0),
             _ => false,
         }
     }
 
     pub(crate) fn is_truthy(&self) -> bool {
         use Value::*;
+        !matches!(self, Bool(false) | Nil)
+    }
+
+    /// Pushes the address of the heap object this value refers to, if any.
+    pub(crate) fn trace(&self, edges: &mut Vec<usize>) {
         match self {
-            Bool(false) | Nil => false,
-            _ => true,
+            Value::BuiltinMethod(m) => m.receiver.trace(edges),
+            Value::Class(c) => edges.push(gc::address(&c.0)),
+            Value::Function(f) => edges.push(gc::address(&f.closure)),
+            Value::Instance(i) => edges.push(gc::address(&i.0)),
+            Value::List(l) => edges.push(gc::address(&l.0)),
+            Value::Map(m) => edges.push(gc::address(&m.0)),
+            _ => (),
         }
     }
 }
@@ -123,9 +177,12 @@ impl Display for Value {
         use Value::*;
         match self {
             Bool(b) => write!(f, "{}", b),
+            BuiltinMethod(_) => write!(f, "<native fn>"),
             Class(c) => write!(f, "{}", c),
             Function(fnc) => write!(f, "{}", fnc),
             Instance(i) => write!(f, "{}", i),
+            List(l) => write!(f, "{}", l),
+            Map(m) => write!(f, "{}", m),
             NativeFn(_) => write!(f, "<native fn>"),
             Nil => write!(f, "nil"),
             Number(n) => write!(f, "{}", n),
@@ -134,49 +191,70 @@ impl Display for Value {
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct NativeFn<F> {
-    pub(crate) body: F,
-}
-
-impl <F> Debug for NativeFn<F> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native fn>")
-    }
-}
-
-impl <F> PartialEq for NativeFn<F> {
-    fn eq(&self, _other: &Self) -> bool {
-        false
-    }
+/// A handle to a host function registered with `Interpreter::define_native`.
+/// The body itself lives in the interpreter, so the handle remembers which
+/// interpreter that was: a host can pass it to another one, which has no
+/// way to call it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NativeFn {
+    pub(crate) name: String,
+    pub(crate) arity: Arity,
+    pub(crate) interpreter: usize,
+    pub(crate) id: usize,
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) struct Function {
-    pub(crate) declaration: stmt::Function,
+pub struct Function {
+    pub(crate) declaration: Rc<stmt::Function>,
     pub(crate) closure: Rc<RefCell<Environment>>,
     pub(crate) is_init: bool,
 }
 
 impl Function {
-    pub(crate) fn new(declaration: stmt::Function, closure: Rc<RefCell<Environment>>, is_init: bool) -> Self {
+    pub(crate) fn new(declaration: Rc<stmt::Function>, closure: Rc<RefCell<Environment>>, is_init: bool) -> Self {
         Self { declaration, closure, is_init }
     }
 
-    pub(crate) fn binding(&self, i: InstancePointer) -> Function {
+    pub(crate) fn binding(&self, i: InstancePointer, heap: &mut Heap) -> std::result::Result<Function, OutOfMemory> {
         let mut env = Environment::from(&self.closure);
         env.define("this", Value::Instance(i));
-        Function {
-            declaration: self.declaration.clone(),
-            closure: Rc::new(RefCell::new(env)),
+        Ok(Function {
+            declaration: Rc::clone(&self.declaration),
+            closure: heap.alloc(env)?,
             is_init: self.is_init
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        match self.declaration.name.kind {
+            TokenKind::Fun => "anonymous",
+            _ => &self.declaration.name.lexeme,
         }
     }
 }
 
 impl fmt::Display for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<fn {}>", self.declaration.name.lexeme)
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// Everything declared in a class body, keyed by name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ClassMembers {
+    pub(crate) methods: HashMap<String, Value>,
+    pub(crate) getters: HashMap<String, Value>,
+    pub(crate) setters: HashMap<String, Value>,
+    // Called on the class itself rather than its instances.
+    pub(crate) static_methods: HashMap<String, Value>,
+}
+
+impl ClassMembers {
+    fn all(&self) -> impl Iterator<Item = &Value> {
+        self.methods.values()
+            .chain(self.getters.values())
+            .chain(self.setters.values())
+            .chain(self.static_methods.values())
     }
 }
 
@@ -184,84 +262,377 @@ impl fmt::Display for Function {
 pub(crate) struct Class {
     pub(crate) name: String,
     superclass: Option<ClassPointer>,
-    fields: HashMap<String, Value>,
+    members: ClassMembers,
+    // Whether this is the built-in Error class, which takes a message when
+    // it has no initializer of its own.
+    is_error: bool,
+}
+
+impl Trace for Class {
+    fn trace(&self, edges: &mut Vec<usize>) {
+        edges.extend(self.superclass.iter().map(|s| gc::address(&s.0)));
+        for value in self.members.all() {
+            value.trace(edges);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.superclass = None;
+        self.members = ClassMembers::default();
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Class>() + self.name.capacity() + self.members.all().map(Value::size).sum::<usize>()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) struct ClassPointer(Rc<RefCell<Class>>);
+pub struct ClassPointer(Rc<RefCell<Class>>);
 
 impl ClassPointer {
-    fn new(name: String, superclass: Option<ClassPointer>, fields: HashMap<String, Value>) -> Self {
-        let class = Class { name, superclass, fields };
-        Self(Rc::new(RefCell::new(class)))
+    pub(crate) fn new(name: String, superclass: Option<ClassPointer>, members: ClassMembers, heap: &mut Heap) -> std::result::Result<Self, OutOfMemory> {
+        let class = Class { name, superclass, members, is_error: false };
+        heap.alloc(class).map(Self)
     }
 
-    pub(crate) fn get_field(&self, name: &str) -> Option<Value> {
+    /// The class runtime errors are turned into when they're caught, which
+    /// scripts can also instantiate or inherit from themselves.
+    pub(crate) fn new_error_class(heap: &mut Heap) -> std::result::Result<Self, OutOfMemory> {
+        let class = Class { name: "Error".into(), superclass: None, members: ClassMembers::default(), is_error: true };
+        heap.alloc(class).map(Self)
+    }
+
+    /// Whether this is the Error class or inherits from it.
+    pub(crate) fn is_error(&self) -> bool {
         let class = self.0.borrow();
-        class.fields.get(name)
+        class.is_error || class.superclass.as_ref().is_some_and(ClassPointer::is_error)
+    }
+
+    /// Finds the member `name` in the table picked out by `table`, looking
+    /// through the superclasses if this class doesn't declare it.
+    fn lookup(&self, name: &str, table: fn(&ClassMembers) -> &HashMap<String, Value>) -> Option<Value> {
+        let class = self.0.borrow();
+        table(&class.members).get(name)
             .cloned()
-            .or_else(|| class.superclass.as_ref().and_then(|s| s.get_field(name)))
+            .or_else(|| class.superclass.as_ref().and_then(|s| s.lookup(name, table)))
     }
 
-    pub(crate) fn instantiate(&self) -> InstancePointer {
+    pub(crate) fn get_field(&self, name: &str) -> Option<Value> {
+        self.lookup(name, |members| &members.methods)
+    }
+
+    pub(crate) fn get_getter(&self, name: &str) -> Option<Value> {
+        self.lookup(name, |members| &members.getters)
+    }
+
+    pub(crate) fn get_static(&self, name: &Token) -> Result<Value> {
+        self.lookup(&name.lexeme, |members| &members.static_methods)
+            .ok_or_else(|| Error::runtime(name.clone(), format!("Undefined property {}.", &name.lexeme)))
+    }
+
+    pub(crate) fn name(&self) -> String {
+        self.0.borrow().name.clone()
+    }
+
+    pub(crate) fn instantiate(&self, heap: &mut Heap) -> std::result::Result<InstancePointer, OutOfMemory> {
         InstancePointer::new(Instance {
             class: self.clone(),
-            fields: HashMap::new()
-        })
+            fields: HashMap::new(),
+            origin: None,
+        }, heap)
     }
 }
 
 impl Display for ClassPointer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} instance", self.0.borrow().name)
+        write!(f, "{}", self.0.borrow().name)
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub(crate) struct Instance {
     class: ClassPointer,
     fields: HashMap<String, Value>,
+    // The runtime error this instance was made from when it was caught, so
+    // that throwing it again carries on with the original.
+    origin: Option<Rc<Error>>,
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        self.class == other.class && self.fields == other.fields
+    }
 }
 
 impl Instance {
     fn get_field(&self, name: &str) -> Option<Value> {
-        self.fields.get(name).map(|f| f.clone())
+        self.fields.get(name).cloned()
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, edges: &mut Vec<usize>) {
+        edges.push(gc::address(&self.class.0));
+        for value in self.fields.values() {
+            value.trace(edges);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Instance>() + fields_size(&self.fields)
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) struct InstancePointer(Rc<RefCell<Instance>>);
+pub struct InstancePointer(Rc<RefCell<Instance>>);
 
 impl InstancePointer {
-    pub(crate) fn new(instance: Instance) -> Self {
-        Self(Rc::new(RefCell::new(instance)))
+    pub(crate) fn new(instance: Instance, heap: &mut Heap) -> std::result::Result<Self, OutOfMemory> {
+        heap.alloc(instance).map(Self)
     }
 
-    pub(crate) fn get(&self, name: &Token) -> Result<Value> {
+    pub(crate) fn get(&self, name: &Token, heap: &mut Heap) -> Result<Value> {
         let instance = self.0.borrow();
         instance.get_field(&name.lexeme)
+            .map(Ok)
             .or_else(|| {
                 let field = instance.class.get_field(&name.lexeme);
                 if let Some(Value::Function(method)) = field {
-                    Some(Value::Function(method.binding(self.clone())))
-                } else { field }
+                    Some(method.binding(self.clone(), heap).map(Value::Function).map_err(|e| e.at(name)))
+                } else { field.map(Ok) }
             })
-            .ok_or_else(||
-                Error::runtime(
+            .unwrap_or_else(||
+                Err(Error::runtime(
                     name.clone(),
                     format!("Undefined property {}.", &name.lexeme)
-                )
+                ))
             )
     }
 
-    pub(crate) fn set(&self, name: &Token, value: &Value) {
+    /// The getter for `name` bound to this instance, unless it's shadowed
+    /// by a field.
+    pub(crate) fn getter(&self, name: &str, heap: &mut Heap) -> std::result::Result<Option<Function>, OutOfMemory> {
+        if self.0.borrow().fields.contains_key(name) {
+            return Ok(None)
+        }
+        self.accessor(name, |members| &members.getters, heap)
+    }
+
+    /// The setter for `name` bound to this instance.
+    pub(crate) fn setter(&self, name: &str, heap: &mut Heap) -> std::result::Result<Option<Function>, OutOfMemory> {
+        self.accessor(name, |members| &members.setters, heap)
+    }
+
+    fn accessor(
+        &self,
+        name: &str,
+        table: fn(&ClassMembers) -> &HashMap<String, Value>,
+        heap: &mut Heap
+    ) -> std::result::Result<Option<Function>, OutOfMemory> {
+        let class = self.0.borrow().class.clone();
+        match class.lookup(name, table) {
+            Some(Value::Function(accessor)) => accessor.binding(self.clone(), heap).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether this is an instance of the Error class or one of its
+    /// subclasses.
+    pub(crate) fn is_error(&self) -> bool {
+        self.0.borrow().class.is_error()
+    }
+
+    /// Remembers that this instance was made from catching `error`.
+    pub(crate) fn set_origin(&self, error: Error) {
+        self.0.borrow_mut().origin = Some(Rc::new(error));
+    }
+
+    /// The runtime error this instance was made from, if it was caught.
+    pub(crate) fn origin(&self) -> Option<Rc<Error>> {
+        self.0.borrow().origin.clone()
+    }
+
+    pub(crate) fn field(&self, name: &str) -> Option<Value> {
+        self.0.borrow().get_field(name)
+    }
+
+    pub(crate) fn set(&self, name: &str, value: &Value) {
         let mut instance = self.0.borrow_mut();
-        instance.fields.insert(name.lexeme.clone(), value.clone());
+        instance.fields.insert(name.to_string(), value.clone());
     }
 }
 
 impl Display for InstancePointer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} instance", self.0.borrow().class)
+        let instance = self.0.borrow();
+        match instance.fields.get("message") {
+            Some(message) if instance.class.is_error() => write!(f, "{}", message),
+            _ => write!(f, "{} instance", instance.class),
+        }
+    }
+}
+
+impl Trace for Vec<Value> {
+    fn trace(&self, edges: &mut Vec<usize>) {
+        for value in self.iter() {
+            value.trace(edges);
+        }
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+
+    fn size(&self) -> usize {
+        mem::size_of::<Vec<Value>>() + self.iter().map(Value::size).sum::<usize>()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListPointer(pub(crate) Rc<RefCell<Vec<Value>>>);
+
+impl ListPointer {
+    pub(crate) fn new(elements: Vec<Value>, heap: &mut Heap) -> std::result::Result<Self, OutOfMemory> {
+        heap.alloc(elements).map(Self)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Value {
+        self.0.borrow()[index].clone()
+    }
+
+    pub(crate) fn set(&self, index: usize, value: Value) {
+        self.0.borrow_mut()[index] = value;
+    }
+}
+
+impl Display for ListPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_once(f, gc::address(&self.0), "[...]", |f| {
+            let elements: Vec<_> = self.0.borrow().iter().map(Value::to_string).collect();
+            write!(f, "[{}]", elements.join(", "))
+        })
+    }
+}
+
+thread_local! {
+    // The objects part way through being displayed, innermost last.
+    static DISPLAYING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// Displays the object at `address` with `display`, unless it contains
+/// itself and is already being displayed further out, in which case it's
+/// shown as `cycle` instead.
+fn display_once(
+    f: &mut fmt::Formatter<'_>,
+    address: usize,
+    cycle: &str,
+    display: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    if !DISPLAYING.with(|displaying| displaying.borrow_mut().insert(address)) {
+        return write!(f, "{}", cycle)
     }
+    let result = display(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().remove(&address));
+    result
+}
+
+/// The entries of a Lox map, which remembers the order its keys were first
+/// inserted in so that iterating over it is deterministic.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Map {
+    entries: Vec<(Literal, Value)>,
+    index: HashMap<Literal, usize>,
+}
+
+impl Map {
+    pub(crate) fn get(&self, key: &Literal) -> Option<&Value> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub(crate) fn insert(&mut self, key: Literal, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            },
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &Literal) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut().filter(|position| **position > i) {
+            *position -= 1;
+        }
+        Some(value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &Literal> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl Trace for Map {
+    fn trace(&self, edges: &mut Vec<usize>) {
+        for value in self.values() {
+            value.trace(edges);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    fn size(&self) -> usize {
+        // Every key is stored twice, once in the entries and once in the
+        // index.
+        let entries: usize = self.entries.iter()
+            .map(|(key, value)| 2 * key.size() + mem::size_of::<usize>() + value.size())
+            .sum();
+        mem::size_of::<Map>() + entries
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapPointer(pub(crate) Rc<RefCell<Map>>);
+
+impl MapPointer {
+    pub(crate) fn new(map: Map, heap: &mut Heap) -> std::result::Result<Self, OutOfMemory> {
+        heap.alloc(map).map(Self)
+    }
+}
+
+impl Display for MapPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_once(f, gc::address(&self.0), "{...}", |f| {
+            let map = self.0.borrow();
+            let entries: Vec<_> = map.entries.iter()
+                .map(|(key, value)| format!("{}: {}", Value::from(key.clone()), value))
+                .collect();
+            write!(f, "{{{}}}", entries.join(", "))
+        })
+    }
+}
+
+/// Roughly how many bytes the entries of a table of named values take up.
+pub(crate) fn fields_size(fields: &HashMap<String, Value>) -> usize {
+    fields.iter().map(|(name, value)| name.capacity() + value.size()).sum()
 }
\ No newline at end of file
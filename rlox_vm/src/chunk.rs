@@ -0,0 +1,217 @@
+use std::{
+    convert::TryFrom,
+    fmt::Write,
+};
+
+use crate::value::Value;
+
+macro_rules! opcodes {
+    ($($op:ident),* $(,)?) => {
+        /// A single instruction. Any operands follow the opcode in the
+        /// chunk: constants, slots and argument counts take one byte and
+        /// jump offsets take two.
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($op),*
+        }
+
+        impl TryFrom<u8> for OpCode {
+            type Error = u8;
+
+            fn try_from(byte: u8) -> Result<Self, u8> {
+                const ALL: &[OpCode] = &[$(OpCode::$op),*];
+                ALL.get(byte as usize).copied().ok_or(byte)
+            }
+        }
+    };
+}
+
+opcodes! {
+    Constant, Nil, True, False, Pop,
+    GetLocal, SetLocal, GetGlobal, DefineGlobal, SetGlobal,
+    GetUpvalue, SetUpvalue, GetProperty, SetProperty, GetSuper,
+    Equal, Greater, Less,
+    Add, Subtract, Multiply, Divide, Not, Negate,
+    Print, Jump, JumpIfFalse, Loop,
+    Call, Invoke, SuperInvoke, Closure, CloseUpvalue, Return,
+    Class, Inherit, Method,
+}
+
+/// A compiled sequence of instructions along with the constants they refer
+/// to and the source line each byte came from.
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub(crate) code: Vec<u8>,
+    pub(crate) constants: Vec<Value>,
+    // Run-length encoded, since most lines produce several bytes in a row:
+    // each entry is a line and the number of bytes which came from it.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        match self.lines.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => self.lines.push((line, 1)),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line)
+    }
+
+    /// Adds `value` to the constant pool, returning its index.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// The source line of the byte at `offset`.
+    pub fn line(&self, offset: usize) -> usize {
+        let mut remaining = offset;
+        for &(line, count) in self.lines.iter() {
+            if remaining < count {
+                return line
+            }
+            remaining -= count;
+        }
+        self.lines.last().map(|&(line, _)| line).unwrap_or(0)
+    }
+
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset = self.disassemble_instruction(offset, &mut out);
+        }
+        out
+    }
+
+    /// Writes the instruction at `offset` to `out`, returning the offset of
+    /// the next instruction.
+    pub fn disassemble_instruction(&self, offset: usize, out: &mut String) -> usize {
+        let _ = write!(out, "{:04} ", offset);
+        if offset > 0 && self.line(offset) == self.line(offset - 1) {
+            out.push_str("   | ");
+        } else {
+            let _ = write!(out, "{:4} ", self.line(offset));
+        }
+
+        let op = match OpCode::try_from(self.code[offset]) {
+            Ok(op) => op,
+            Err(byte) => {
+                let _ = writeln!(out, "Unknown opcode {}", byte);
+                return offset + 1
+            },
+        };
+        let name = format!("{:?}", op);
+
+        use OpCode::*;
+        match op {
+            Constant | GetGlobal | DefineGlobal | SetGlobal | GetProperty | SetProperty
+            | GetSuper | Class | Method => {
+                let constant = self.code[offset + 1];
+                let _ = writeln!(out, "{:<16} {:4} '{}'", name, constant, self.constants[constant as usize]);
+                offset + 2
+            },
+            GetLocal | SetLocal | GetUpvalue | SetUpvalue | Call => {
+                let _ = writeln!(out, "{:<16} {:4}", name, self.code[offset + 1]);
+                offset + 2
+            },
+            Jump | JumpIfFalse | Loop => {
+                let jump = u16::from_be_bytes([self.code[offset + 1], self.code[offset + 2]]) as usize;
+                let target = if op == Loop { offset + 3 - jump } else { offset + 3 + jump };
+                let _ = writeln!(out, "{:<16} {:4} -> {}", name, offset, target);
+                offset + 3
+            },
+            Invoke | SuperInvoke => {
+                let constant = self.code[offset + 1];
+                let arg_count = self.code[offset + 2];
+                let _ = writeln!(
+                    out,
+                    "{:<16} ({} args) {:4} '{}'",
+                    name, arg_count, constant, self.constants[constant as usize]
+                );
+                offset + 3
+            },
+            Closure => {
+                let constant = self.code[offset + 1];
+                let function = &self.constants[constant as usize];
+                let _ = writeln!(out, "{:<16} {:4} {}", name, constant, function);
+
+                let upvalue_count = match function {
+                    Value::Function(f) => f.upvalue_count,
+                    _ => 0,
+                };
+                let mut offset = offset + 2;
+                for _ in 0..upvalue_count {
+                    let kind = if self.code[offset] == 1 { "local" } else { "upvalue" };
+                    let _ = writeln!(out, "{:04}    |                     {} {}", offset, kind, self.code[offset + 1]);
+                    offset += 2;
+                }
+                offset
+            },
+            _ => {
+                let _ = writeln!(out, "{}", name);
+                offset + 1
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        assert_eq!(Ok(OpCode::Constant), OpCode::try_from(OpCode::Constant as u8));
+        assert_eq!(Ok(OpCode::Method), OpCode::try_from(OpCode::Method as u8));
+        assert_eq!(Err(255), OpCode::try_from(255));
+    }
+
+    #[test]
+    fn remembers_the_line_of_every_byte() {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Pop, 1);
+        chunk.write_op(OpCode::True, 3);
+        chunk.write_op(OpCode::Return, 4);
+        assert_eq!(vec![1, 1, 3, 4], (0..4).map(|o| chunk.line(o)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn disassembles_instructions() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::Number(1.5));
+        chunk.write_op(OpCode::Constant, 1);
+        chunk.write(constant as u8, 1);
+        chunk.write_op(OpCode::Return, 2);
+        assert_eq!(
+            "== test ==\n0000    1 Constant            0 '1.5'\n0002    2 Return\n",
+            chunk.disassemble("test")
+        );
+    }
+}
\ No newline at end of file
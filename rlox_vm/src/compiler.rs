@@ -0,0 +1,994 @@
+use std::{
+    mem,
+    rc::Rc,
+    vec,
+};
+
+use rlox_treewalk::{
+    error::{Error, Result},
+    scanner::Scanner,
+    token::{Token, TokenKind},
+};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    value::{Function, Value},
+};
+
+// Slots, constants and upvalues are all addressed by a single byte.
+const MAX_OPERAND: usize = u8::MAX as usize + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        use Precedence::*;
+        match self {
+            None => Assignment,
+            Assignment => Or,
+            Or => And,
+            And => Equality,
+            Equality => Comparison,
+            Comparison => Term,
+            Term => Factor,
+            Factor => Unary,
+            Unary => Call,
+            Call | Primary => Primary,
+        }
+    }
+
+    fn of(kind: &TokenKind) -> Self {
+        use TokenKind::*;
+        match kind {
+            LeftParen | Dot => Precedence::Call,
+            Minus | Plus => Precedence::Term,
+            Slash | Star => Precedence::Factor,
+            BangEqual | EqualEqual => Precedence::Equality,
+            Greater | GreaterEqual | Less | LessEqual => Precedence::Comparison,
+            And => Precedence::And,
+            Or => Precedence::Or,
+            _ => Precedence::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FunctionKind {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+struct Local {
+    name: String,
+    // `None` until the variable's initializer has been compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+/// The state of a function which is part way through being compiled.
+/// These form a stack, with the innermost function at the top.
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<Rc<str>>) -> Self {
+        // Slot zero holds the function being called, or the receiver in
+        // methods, where it's reachable as `this`.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        FunctionState {
+            function: Function { name, ..Function::default() },
+            kind,
+            locals: vec![Local { name: slot_zero.into(), depth: Some(0), is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// Compiles `source` to the function for its top-level script in a single
+/// pass, with no intermediate syntax tree.
+pub fn compile(source: &str) -> std::result::Result<Function, Vec<Error>> {
+    let mut compiler = Compiler::new(source);
+    while !compiler.matches(&TokenKind::EndOfFile) {
+        compiler.declaration();
+    }
+    let (function, _) = compiler.end_function();
+
+    if compiler.errors.is_empty() {
+        Ok(function)
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+struct Compiler {
+    tokens: vec::IntoIter<Result<Token>>,
+    current: Token,
+    previous: Token,
+    errors: Vec<Error>,
+    panic_mode: bool,
+    functions: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+}
+
+impl Compiler {
+    fn new(source: &str) -> Self {
+        let mut tokens = Scanner::new(source).scan_tokens().into_iter();
+        let mut errors = Vec::new();
+        let first = next_token(&mut tokens, &mut errors)
+            .expect("The scanner always produces an end of file token");
+
+        Compiler {
+            tokens,
+            current: first.clone(),
+            previous: first,
+            panic_mode: !errors.is_empty(),
+            errors,
+            functions: vec![FunctionState::new(FunctionKind::Script, None)],
+            classes: Vec::new(),
+        }
+    }
+
+    // Token handling
+
+    fn advance(&mut self) {
+        let error_count = self.errors.len();
+        let next = next_token(&mut self.tokens, &mut self.errors)
+            .unwrap_or_else(|| self.current.clone());
+        self.previous = mem::replace(&mut self.current, next);
+
+        // The bad characters have been skipped, so whatever follows them is
+        // likely to be an error too.
+        if self.errors.len() > error_count {
+            self.panic_mode = true;
+        }
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.current.kind() == kind
+    }
+
+    fn matches(&mut self, kind: &TokenKind) -> bool {
+        if !self.check(kind) {
+            return false
+        }
+        self.advance();
+        true
+    }
+
+    fn consume(&mut self, kind: &TokenKind, message: &str) {
+        if self.check(kind) {
+            self.advance();
+        } else {
+            self.error_at_current(message);
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        let token = self.previous.clone();
+        self.error_at(token, message)
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        let token = self.current.clone();
+        self.error_at(token, message)
+    }
+
+    fn error_at(&mut self, token: Token, message: &str) {
+        // Only report the first error until we've resynchronised, since
+        // the rest are likely to be caused by it.
+        if self.panic_mode {
+            return
+        }
+        self.panic_mode = true;
+        self.errors.push(Error::syntactic(token, message));
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while !self.check(&TokenKind::EndOfFile) {
+            if self.previous.kind() == &TokenKind::Semicolon {
+                return
+            }
+            use TokenKind::*;
+            match self.current.kind() {
+                Class | Fun | Var | For | If | While | Print | Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    // Emitting bytecode
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("There is always a function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.previous.line();
+        self.chunk().write(byte, line)
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8)
+    }
+
+    fn emit_op_with(&mut self, op: OpCode, operand: u8) {
+        self.emit_op(op);
+        self.emit_byte(operand);
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_op_with(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    /// Emits a jump with a placeholder offset, returning the offset of the
+    /// placeholder so that it can be patched once the target is known.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.chunk().len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // Jump over the two bytes of the offset itself.
+        let jump = self.chunk().len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+
+        let [high, low] = (jump as u16).to_be_bytes();
+        let code = &mut self.chunk().code;
+        code[offset] = high;
+        code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+
+        let jump = self.chunk().len() - loop_start + 2;
+        if jump > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+
+        let [high, low] = (jump as u16).to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let constant = self.chunk().add_constant(value);
+        if constant >= MAX_OPERAND {
+            self.error("Too many constants in one chunk.");
+            return 0
+        }
+        constant as u8
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_op_with(OpCode::Constant, constant)
+    }
+
+    fn end_function(&mut self) -> (Function, Vec<UpvalueRef>) {
+        self.emit_return();
+        let mut state = self.functions.pop().expect("There is always a function being compiled");
+        state.function.upvalue_count = state.upvalues.len();
+        (state.function, state.upvalues)
+    }
+
+    // Declarations
+
+    fn declaration(&mut self) {
+        if self.matches(&TokenKind::Class) {
+            self.class_declaration();
+        } else if self.matches(&TokenKind::Fun) {
+            self.fun_declaration();
+        } else if self.matches(&TokenKind::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(&TokenKind::Identifier, "Expect class name.");
+        let class_name = self.previous.clone();
+        let name_constant = self.identifier_constant(class_name.lexeme());
+        self.declare_variable();
+
+        self.emit_op_with(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState { has_superclass: false });
+
+        if self.matches(&TokenKind::Less) {
+            self.consume(&TokenKind::Identifier, "Expect superclass name.");
+            self.variable(false);
+
+            if class_name.lexeme() == self.previous.lexeme() {
+                self.error("A class can't inherit from itself.");
+            }
+
+            // Methods capture the superclass through this scope, which is
+            // how `super` is resolved.
+            self.begin_scope();
+            self.add_local("super");
+            self.define_variable(0);
+
+            self.named_variable(class_name.lexeme(), false);
+            self.emit_op(OpCode::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(class_name.lexeme(), false);
+        self.consume(&TokenKind::LeftBrace, "Expect '{' before class body.");
+        while !self.check(&TokenKind::RightBrace) && !self.check(&TokenKind::EndOfFile) {
+            self.method();
+        }
+        self.consume(&TokenKind::RightBrace, "Expect '}' after class body.");
+        self.emit_op(OpCode::Pop);
+
+        if let Some(ClassState { has_superclass: true }) = self.classes.pop() {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.consume(&TokenKind::Identifier, "Expect method name.");
+        let constant = self.previous_identifier_constant();
+        let kind = if self.previous.lexeme() == "init" {
+            FunctionKind::Initializer
+        } else {
+            FunctionKind::Method
+        };
+        self.function(kind);
+        self.emit_op_with(OpCode::Method, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // A function can refer to itself, so it's defined before its body
+        // is compiled.
+        self.mark_initialized();
+        self.function(FunctionKind::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, kind: FunctionKind) {
+        let name = self.previous.lexeme().into();
+        self.functions.push(FunctionState::new(kind, Some(name)));
+        self.begin_scope();
+
+        self.consume(&TokenKind::LeftParen, "Expect '(' after function name.");
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                self.state().function.arity += 1;
+                if self.state().function.arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+
+                if !self.matches(&TokenKind::Comma) {
+                    break
+                }
+            }
+        }
+        self.consume(&TokenKind::RightParen, "Expect ')' after parameters.");
+        self.consume(&TokenKind::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        // No need to end the scope, since the whole frame is discarded
+        // when the function returns.
+        let (function, upvalues) = self.end_function();
+        let constant = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit_op_with(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+
+        if self.matches(&TokenKind::Equal) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(&TokenKind::Semicolon, "Expect ';' after variable declaration.");
+
+        self.define_variable(global);
+    }
+
+    // Statements
+
+    fn statement(&mut self) {
+        if self.matches(&TokenKind::Print) {
+            self.print_statement();
+        } else if self.matches(&TokenKind::For) {
+            self.for_statement();
+        } else if self.matches(&TokenKind::If) {
+            self.if_statement();
+        } else if self.matches(&TokenKind::Return) {
+            self.return_statement();
+        } else if self.matches(&TokenKind::While) {
+            self.while_statement();
+        } else if self.matches(&TokenKind::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn block(&mut self) {
+        while !self.check(&TokenKind::RightBrace) && !self.check(&TokenKind::EndOfFile) {
+            self.declaration();
+        }
+        self.consume(&TokenKind::RightBrace, "Expect '}' after block.");
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(&TokenKind::Semicolon, "Expect ';' after value.");
+        self.emit_op(OpCode::Print);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(&TokenKind::Semicolon, "Expect ';' after expression.");
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(&TokenKind::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+
+        if self.matches(&TokenKind::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.chunk().len();
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(&TokenKind::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(&TokenKind::LeftParen, "Expect '(' after 'for'.");
+        if self.matches(&TokenKind::Semicolon) {
+            // No initializer.
+        } else if self.matches(&TokenKind::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.chunk().len();
+        let mut exit_jump = None;
+        if !self.matches(&TokenKind::Semicolon) {
+            self.expression();
+            self.consume(&TokenKind::Semicolon, "Expect ';' after loop condition.");
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_op(OpCode::Pop);
+        }
+
+        // The increment comes before the body in the source but runs after
+        // it, so the body jumps back to it and it jumps back to the
+        // condition.
+        if !self.matches(&TokenKind::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().len();
+            self.expression();
+            self.emit_op(OpCode::Pop);
+            self.consume(&TokenKind::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(OpCode::Pop);
+        }
+        self.end_scope();
+    }
+
+    fn return_statement(&mut self) {
+        if self.state().kind == FunctionKind::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.matches(&TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.state().kind == FunctionKind::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+            self.expression();
+            self.consume(&TokenKind::Semicolon, "Expect ';' after return value.");
+            self.emit_op(OpCode::Return);
+        }
+    }
+
+    // Expressions
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let can_assign = precedence <= Precedence::Assignment;
+        if !self.prefix(can_assign) {
+            self.error("Expect expression.");
+            return
+        }
+
+        while precedence <= Precedence::of(self.current.kind()) {
+            self.advance();
+            self.infix(can_assign);
+        }
+
+        if can_assign && self.matches(&TokenKind::Equal) {
+            self.error("Invalid assignment target.");
+        }
+    }
+
+    /// Compiles the expression starting with the previous token, returning
+    /// false if no expression can start with it.
+    fn prefix(&mut self, can_assign: bool) -> bool {
+        use TokenKind::*;
+        match self.previous.kind().clone() {
+            LeftParen => self.grouping(),
+            Minus | Bang => self.unary(),
+            Number(n) => self.emit_constant(Value::Number(n.value())),
+            String(s) => self.emit_constant(Value::from(s)),
+            Nil => self.emit_op(OpCode::Nil),
+            True => self.emit_op(OpCode::True),
+            False => self.emit_op(OpCode::False),
+            Identifier => self.variable(can_assign),
+            This => self.this(),
+            Super => self.super_(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn infix(&mut self, can_assign: bool) {
+        use TokenKind::*;
+        match self.previous.kind() {
+            LeftParen => self.call(),
+            Dot => self.dot(can_assign),
+            And => self.and(),
+            Or => self.or(),
+            _ => self.binary(),
+        }
+    }
+
+    fn grouping(&mut self) {
+        self.expression();
+        self.consume(&TokenKind::RightParen, "Expect ')' after expression.");
+    }
+
+    fn unary(&mut self) {
+        let operator = self.previous.kind().clone();
+        self.parse_precedence(Precedence::Unary);
+
+        match operator {
+            TokenKind::Minus => self.emit_op(OpCode::Negate),
+            TokenKind::Bang => self.emit_op(OpCode::Not),
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&mut self) {
+        let operator = self.previous.kind().clone();
+        self.parse_precedence(Precedence::of(&operator).next());
+
+        use TokenKind::*;
+        match operator {
+            BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            },
+            EqualEqual => self.emit_op(OpCode::Equal),
+            Greater => self.emit_op(OpCode::Greater),
+            GreaterEqual => {
+                self.emit_op(OpCode::Less);
+                self.emit_op(OpCode::Not);
+            },
+            Less => self.emit_op(OpCode::Less),
+            LessEqual => {
+                self.emit_op(OpCode::Greater);
+                self.emit_op(OpCode::Not);
+            },
+            Plus => self.emit_op(OpCode::Add),
+            Minus => self.emit_op(OpCode::Subtract),
+            Star => self.emit_op(OpCode::Multiply),
+            Slash => self.emit_op(OpCode::Divide),
+            _ => unreachable!(),
+        }
+    }
+
+    fn and(&mut self) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op(OpCode::Pop);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn call(&mut self) {
+        let arg_count = self.argument_list();
+        self.emit_op_with(OpCode::Call, arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count = 0;
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+
+                if !self.matches(&TokenKind::Comma) {
+                    break
+                }
+            }
+        }
+        self.consume(&TokenKind::RightParen, "Expect ')' after arguments.");
+        arg_count.min(255) as u8
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(&TokenKind::Identifier, "Expect property name after '.'.");
+        let name = self.previous_identifier_constant();
+
+        if can_assign && self.matches(&TokenKind::Equal) {
+            self.expression();
+            self.emit_op_with(OpCode::SetProperty, name);
+        } else if self.matches(&TokenKind::LeftParen) {
+            let arg_count = self.argument_list();
+            self.emit_op_with(OpCode::Invoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_op_with(OpCode::GetProperty, name);
+        }
+    }
+
+    fn this(&mut self) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return
+        }
+        self.variable(false);
+    }
+
+    fn super_(&mut self) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(ClassState { has_superclass: false }) =>
+                self.error("Can't use 'super' in a class with no superclass."),
+            _ => (),
+        }
+
+        self.consume(&TokenKind::Dot, "Expect '.' after 'super'.");
+        self.consume(&TokenKind::Identifier, "Expect superclass method name.");
+        let name = self.previous_identifier_constant();
+
+        self.named_variable("this", false);
+        if self.matches(&TokenKind::LeftParen) {
+            let arg_count = self.argument_list();
+            self.named_variable("super", false);
+            self.emit_op_with(OpCode::SuperInvoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable("super", false);
+            self.emit_op_with(OpCode::GetSuper, name);
+        }
+    }
+
+    // Variables
+
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.previous.lexeme().to_string();
+        self.named_variable(&name, can_assign)
+    }
+
+    fn named_variable(&mut self, name: &str, can_assign: bool) {
+        let innermost = self.functions.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(innermost, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(innermost, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            (OpCode::GetGlobal, OpCode::SetGlobal, self.identifier_constant(name))
+        };
+
+        if can_assign && self.matches(&TokenKind::Equal) {
+            self.expression();
+            self.emit_op_with(set, operand);
+        } else {
+            self.emit_op_with(get, operand);
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u8 {
+        self.make_constant(Value::from(name))
+    }
+
+    fn previous_identifier_constant(&mut self) -> u8 {
+        let name = self.previous.lexeme().to_string();
+        self.identifier_constant(&name)
+    }
+
+    fn resolve_local(&mut self, function: usize, name: &str) -> Option<u8> {
+        let (slot, local) = self.functions[function].locals.iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)?;
+
+        if local.depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    /// Finds `name` in one of the functions enclosing `function`, threading
+    /// it through every function in between as an upvalue.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        if function == 0 {
+            return None
+        }
+
+        if let Some(local) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(function, local, true))
+        }
+
+        let upvalue = self.resolve_upvalue(function - 1, name)?;
+        Some(self.add_upvalue(function, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8
+        }
+
+        if upvalues.len() == MAX_OPERAND {
+            self.error("Too many closure variables in function.");
+            return 0
+        }
+        let upvalues = &mut self.functions[function].upvalues;
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn parse_variable(&mut self, message: &str) -> u8 {
+        self.consume(&TokenKind::Identifier, message);
+
+        self.declare_variable();
+        if self.state().scope_depth > 0 {
+            return 0
+        }
+
+        self.previous_identifier_constant()
+    }
+
+    fn declare_variable(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return
+        }
+
+        let scope_depth = state.scope_depth;
+        let name = self.previous.lexeme().to_string();
+        let already_declared = self.state().locals.iter()
+            .rev()
+            .take_while(|local| local.depth.map(|d| d >= scope_depth).unwrap_or(true))
+            .any(|local| local.name == name);
+        if already_declared {
+            self.error("A variable with this name already exists in this scope.");
+        }
+
+        self.add_local(&name);
+    }
+
+    fn add_local(&mut self, name: &str) {
+        if self.state().locals.len() == MAX_OPERAND {
+            self.error("Too many local variables in function.");
+            return
+        }
+        self.state().locals.push(Local { name: name.into(), depth: None, is_captured: false });
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return
+        }
+        self.emit_op_with(OpCode::DefineGlobal, global);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state().scope_depth -= 1;
+
+        loop {
+            let state = self.state();
+            let depth = state.scope_depth;
+            let is_captured = match state.locals.last() {
+                Some(Local { depth: Some(d), is_captured, .. }) if *d > depth => *is_captured,
+                _ => break,
+            };
+            state.locals.pop();
+            self.emit_op(if is_captured { OpCode::CloseUpvalue } else { OpCode::Pop });
+        }
+    }
+}
+
+/// The next token from the scanner, recording any lexical errors before it.
+fn next_token(tokens: &mut vec::IntoIter<Result<Token>>, errors: &mut Vec<Error>) -> Option<Token> {
+    for token in tokens {
+        match token {
+            Ok(token) => return Some(token),
+            Err(e) => errors.push(e),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<String> {
+        match compile(source) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn compiles_a_script() {
+        let function = compile("var a = 1; print a + 2;").unwrap();
+        let chunk = function.chunk();
+        assert_eq!(Some(&(OpCode::Return as u8)), chunk.code().last());
+        assert_eq!(vec![Value::from("a"), Value::Number(1.0), Value::from("a"), Value::Number(2.0)], chunk.constants());
+    }
+
+    #[test]
+    fn reports_every_error() {
+        assert_eq!(
+            vec![
+                "[line 1] Error at =: Invalid assignment target.",
+                "[line 2] Error at return: Can't return from top-level code.",
+                "[line 3] Error at this: Can't use 'this' outside of a class.",
+                "[line 4] Error at end: Expect ';' after value.",
+            ],
+            errors("1 + 2 = 3;\nreturn 1;\nprint this;\nprint 1")
+        );
+    }
+
+    #[test]
+    fn reports_lexical_errors() {
+        assert_eq!(vec!["[line 1] Error: Unexpected character '@'"], errors("var a = @;"));
+    }
+
+    #[test]
+    fn rejects_locals_read_in_their_own_initializer() {
+        assert_eq!(
+            vec!["[line 1] Error at a: Can't read local variable in its own initializer."],
+            errors("{ var a = a; }")
+        );
+    }
+}
\ No newline at end of file
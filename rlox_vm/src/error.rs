@@ -0,0 +1,69 @@
+use std::{
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+use rlox_treewalk::error::Error;
+
+#[derive(Debug)]
+pub enum InterpretError {
+    /// Every lexical and syntax error in the source. The compiler
+    /// synchronises after each error, so there may be several.
+    Compile(Vec<Error>),
+    Runtime(RuntimeError),
+}
+
+impl std::error::Error for InterpretError {}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::Compile(errors) => {
+                let lines: Vec<_> = errors.iter().map(Error::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            },
+            InterpretError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A runtime error along with the call stack at the point it was raised,
+/// innermost call first.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub(crate) message: String,
+    pub(crate) trace: Vec<TraceFrame>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TraceFrame {
+    pub(crate) line: usize,
+    // `None` for the top-level script.
+    pub(crate) function: Option<Rc<str>>,
+}
+
+impl RuntimeError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The line being executed when the error was raised.
+    pub fn line(&self) -> usize {
+        self.trace.first().map(|frame| frame.line).unwrap_or(0)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in self.trace.iter() {
+            match &frame.function {
+                Some(name) => write!(f, "\n[line {}] in {}()", frame.line, name)?,
+                None => write!(f, "\n[line {}] in script", frame.line)?,
+            }
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
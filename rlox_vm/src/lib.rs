@@ -0,0 +1,8 @@
+pub mod chunk;
+pub mod compiler;
+pub mod error;
+pub mod value;
+pub mod vm;
+
+pub use value::Value;
+pub use vm::Vm;
\ No newline at end of file
@@ -0,0 +1,63 @@
+use rlox_vm::{
+    error::InterpretError,
+    Vm,
+};
+use std::{
+    env,
+    io::{self, Write},
+};
+
+fn main() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+
+    let args: Vec<String> = env::args().collect();
+    match args.len() {
+        1 => run_prompt(&mut stdout, &mut stderr)?,
+        2 => run_file(args[1].as_str(), &mut stdout, &mut stderr)?,
+        _ => {
+            writeln!(stdout, "Usage: rlox_vm [script]")?;
+            std::process::exit(64);
+        },
+    };
+
+    Ok(())
+}
+
+fn run_file(path: &str, out: &mut io::Stdout, err_out: &mut io::Stderr) -> io::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut vm = Vm::new(out);
+    match vm.interpret(contents.as_str()) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            writeln!(err_out, "{}", e)?;
+            match e {
+                InterpretError::Compile(_) => std::process::exit(65),
+                InterpretError::Runtime(_) => std::process::exit(70),
+            }
+        },
+    }
+}
+
+fn run_prompt(out: &mut io::Stdout, err_out: &mut io::Stderr) -> io::Result<()> {
+    let mut buffer = String::new();
+    let stdin = io::stdin();
+
+    let mut vm = Vm::new(io::stdout());
+
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+
+        buffer.clear();
+
+        let num_bytes = stdin.read_line(&mut buffer)?;
+        if num_bytes == 0 { break };
+
+        if let Err(e) = vm.interpret(buffer.as_str()) {
+            writeln!(err_out, "{}", e)?;
+        }
+    }
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,171 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+use rlox_treewalk::callable::Arity;
+
+use crate::chunk::Chunk;
+
+/// A value on the VM's stack. Strings and every kind of object live behind
+/// an `Rc`, so copying a value is always cheap.
+#[derive(Clone)]
+pub enum Value {
+    Bool(bool),
+    BoundMethod(Rc<BoundMethod>),
+    Class(Rc<RefCell<Class>>),
+    Closure(Rc<Closure>),
+    Function(Rc<Function>),
+    Instance(Rc<RefCell<Instance>>),
+    Native(Rc<Native>),
+    Nil,
+    Number(f64),
+    String(Rc<str>),
+}
+
+impl Value {
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        use Value::*;
+        match (self, other) {
+            (Bool(a), Bool(b)) => a == b,
+            (Nil, Nil) => true,
+            (Number(a), Number(b)) => a == b,
+            (String(a), String(b)) => a == b,
+            (BoundMethod(a), BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Class(a), Class(b)) => Rc::ptr_eq(a, b),
+            (Closure(a), Closure(b)) => Rc::ptr_eq(a, b),
+            (Function(a), Function(b)) => Rc::ptr_eq(a, b),
+            (Instance(a), Instance(b)) => Rc::ptr_eq(a, b),
+            (Native(a), Native(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Value::*;
+        match self {
+            Bool(b) => write!(f, "{}", b),
+            BoundMethod(m) => write!(f, "{}", m.method),
+            Class(c) => write!(f, "{}", c.borrow().name),
+            Closure(c) => write!(f, "{}", c),
+            Function(fnc) => write!(f, "{}", fnc),
+            Instance(i) => write!(f, "{} instance", i.borrow().class.borrow().name),
+            Native(_) => write!(f, "<native fn>"),
+            Nil => write!(f, "nil"),
+            Number(n) => write!(f, "{}", n),
+            String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Objects can refer to themselves through their fields, so rather than
+// deriving this we show them the way Lox would print them.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub(crate) arity: usize,
+    pub(crate) upvalue_count: usize,
+    pub(crate) chunk: Chunk,
+    // `None` for the top-level script.
+    pub(crate) name: Option<Rc<str>>,
+}
+
+impl Function {
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A variable captured by a closure. It points at a stack slot for as long
+/// as the variable is on the stack, and holds the value itself afterwards.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Closure {
+    pub(crate) function: Rc<Function>,
+    pub(crate) upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+pub struct Class {
+    pub(crate) name: Rc<str>,
+    pub(crate) methods: HashMap<Rc<str>, Rc<Closure>>,
+}
+
+pub struct Instance {
+    pub(crate) class: Rc<RefCell<Class>>,
+    pub(crate) fields: HashMap<Rc<str>, Value>,
+}
+
+pub struct BoundMethod {
+    pub(crate) receiver: Value,
+    pub(crate) method: Rc<Closure>,
+}
+
+pub type NativeBody = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented by the host. Returning `Err` raises a runtime
+/// error with the given message.
+pub struct Native {
+    pub(crate) arity: Arity,
+    pub(crate) body: Box<NativeBody>,
+}
\ No newline at end of file
@@ -0,0 +1,690 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    io::Write,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rlox_treewalk::callable::Arity;
+
+use crate::{
+    chunk::OpCode,
+    compiler,
+    error::{InterpretError, RuntimeError, TraceFrame},
+    value::{BoundMethod, Class, Closure, Instance, Native, Upvalue, Value},
+};
+
+const FRAMES_MAX: usize = 64;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // The index of the stack slot holding the function being called. Its
+    // arguments and locals follow it.
+    slots: usize,
+}
+
+/// A stack-based virtual machine for running compiled Lox. Globals defined
+/// by one call to `interpret` are visible to every later call.
+pub struct Vm<W> {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    globals: HashMap<Rc<str>, Value>,
+    // The upvalues which still point into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    init_string: Rc<str>,
+    out: W,
+}
+
+impl <W: Write> Vm<W> {
+    pub fn new(out: W) -> Self {
+        let mut vm = Vm {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            init_string: "init".into(),
+            out,
+        };
+
+        vm.define_native("clock", Arity::Exactly(0), |_| {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time since epoch should never be negative")
+                .as_millis();
+            Ok(Value::Number(time as f64))
+        });
+
+        vm
+    }
+
+    pub fn interpret(&mut self, source: &str) -> std::result::Result<(), InterpretError> {
+        let function = compiler::compile(source).map_err(InterpretError::Compile)?;
+        let closure = Rc::new(Closure { function: Rc::new(function), upvalues: Vec::new() });
+
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        self.call(closure, 0)
+            .and_then(|_| self.run())
+            .map_err(InterpretError::Runtime)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
+    }
+
+    pub fn set_global<S: Into<String>, V: Into<Value>>(&mut self, name: S, value: V) {
+        self.globals.insert(name.into().into(), value.into());
+    }
+
+    /// Defines a global function which is implemented by the host. An `Err`
+    /// returned by `body` becomes a runtime error in the calling script.
+    pub fn define_native<S, F>(&mut self, name: S, arity: Arity, body: F)
+    where
+        S: Into<String>,
+        F: Fn(&[Value]) -> std::result::Result<Value, String> + 'static,
+    {
+        let native = Native { arity, body: Box::new(body) };
+        self.set_global(name, Value::Native(Rc::new(native)));
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            let byte = self.read_byte();
+            let op = match OpCode::try_from(byte) {
+                Ok(op) => op,
+                Err(byte) => return Err(self.runtime_error(format!("Unknown opcode {}.", byte))),
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                },
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.push(self.stack[slot].clone());
+                },
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                },
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(value) => self.push(value.clone()),
+                        None => return Err(self.runtime_error(format!("Undefined variable '{}'.", name))),
+                    }
+                },
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                },
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.runtime_error(format!("Undefined variable '{}'.", name))),
+                    }
+                },
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                },
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = self.peek(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                },
+                OpCode::GetProperty => {
+                    let instance = match self.peek(0) {
+                        Value::Instance(instance) => Rc::clone(instance),
+                        _ => return Err(self.runtime_error("Only instances have properties.")),
+                    };
+                    let name = self.read_string();
+
+                    let field = instance.borrow().fields.get(&name).cloned();
+                    match field {
+                        Some(value) => {
+                            self.pop();
+                            self.push(value);
+                        },
+                        None => {
+                            let class = Rc::clone(&instance.borrow().class);
+                            self.bind_method(&class, &name)?;
+                        },
+                    }
+                },
+                OpCode::SetProperty => {
+                    let instance = match self.peek(1) {
+                        Value::Instance(instance) => Rc::clone(instance),
+                        _ => return Err(self.runtime_error("Only instances have fields.")),
+                    };
+                    let name = self.read_string();
+
+                    let value = self.pop();
+                    instance.borrow_mut().fields.insert(name, value.clone());
+                    self.pop();
+                    self.push(value);
+                },
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let superclass = self.pop_class();
+                    self.bind_method(&superclass, &name)?;
+                },
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::Bool(a == b));
+                },
+                OpCode::Greater => self.binary_op(|a, b| Value::Bool(a > b))?,
+                OpCode::Less => self.binary_op(|a, b| Value::Bool(a < b))?,
+                OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::String(a), Value::String(b)) => {
+                        let concatenated = [a.as_ref(), b.as_ref()].concat();
+                        self.pop();
+                        self.pop();
+                        self.push(Value::from(concatenated));
+                    },
+                    (Value::Number(a), Value::Number(b)) => {
+                        let sum = a + b;
+                        self.pop();
+                        self.pop();
+                        self.push(Value::Number(sum));
+                    },
+                    _ => return Err(self.runtime_error("Operands must be two numbers or two strings.")),
+                },
+                OpCode::Subtract => self.binary_op(|a, b| Value::Number(a - b))?,
+                OpCode::Multiply => self.binary_op(|a, b| Value::Number(a * b))?,
+                OpCode::Divide => self.binary_op(|a, b| Value::Number(a / b))?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::Bool(value.is_falsey()));
+                },
+                OpCode::Negate => match self.peek(0) {
+                    Value::Number(n) => {
+                        let negated = -n;
+                        self.pop();
+                        self.push(Value::Number(negated));
+                    },
+                    _ => return Err(self.runtime_error("Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    if let Err(e) = writeln!(self.out, "{}", value) {
+                        return Err(self.runtime_error(e.to_string()))
+                    }
+                },
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset;
+                },
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                },
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset;
+                },
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count).clone(), arg_count)?;
+                },
+                OpCode::Invoke => {
+                    let method = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(&method, arg_count)?;
+                },
+                OpCode::SuperInvoke => {
+                    let method = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.pop_class();
+                    self.invoke_from_class(&superclass, &method, arg_count)?;
+                },
+                OpCode::Closure => {
+                    let function = match self.read_constant() {
+                        Value::Function(function) => function,
+                        _ => unreachable!("Closures are always compiled from functions"),
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            Rc::clone(&self.frame().closure.upvalues[index])
+                        });
+                    }
+                    self.push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                },
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Returning from a function requires a frame");
+                    self.close_upvalues(frame.slots);
+
+                    if self.frames.is_empty() {
+                        self.stack.clear();
+                        return Ok(())
+                    }
+
+                    self.stack.truncate(frame.slots);
+                    self.push(result);
+                },
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = Class { name, methods: HashMap::new() };
+                    self.push(Value::Class(Rc::new(RefCell::new(class))));
+                },
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1) {
+                        Value::Class(class) => Rc::clone(class),
+                        _ => return Err(self.runtime_error("Superclass must be a class.")),
+                    };
+                    let subclass = self.pop_class();
+                    // Copying the methods down now means that lookups never
+                    // have to walk the inheritance chain.
+                    let methods = superclass.borrow().methods.clone();
+                    subclass.borrow_mut().methods.extend(methods);
+                },
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = match self.pop() {
+                        Value::Closure(closure) => closure,
+                        _ => unreachable!("Methods are always compiled to closures"),
+                    };
+                    if let Value::Class(class) = self.peek(0) {
+                        class.borrow_mut().methods.insert(name, method);
+                    }
+                },
+            }
+        }
+    }
+
+    // Reading the current chunk
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Code only runs inside a frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("Code only runs inside a frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low]) as usize
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(s) => s,
+            _ => unreachable!("Names are always compiled to string constants"),
+        }
+    }
+
+    // The stack
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("The compiler never pops an empty stack")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn pop_class(&mut self) -> Rc<RefCell<Class>> {
+        match self.pop() {
+            Value::Class(class) => class,
+            _ => unreachable!("The compiler only emits this where a class is on the stack"),
+        }
+    }
+
+    fn binary_op(&mut self, op: impl Fn(f64, f64) -> Value) -> Result<()> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => {
+                let result = op(*a, *b);
+                self.pop();
+                self.pop();
+                self.push(result);
+                Ok(())
+            },
+            _ => Err(self.runtime_error("Operands must be numbers.")),
+        }
+    }
+
+    // Calls
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<()> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match callee {
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), arg_count)
+            },
+            Value::Class(class) => {
+                let instance = Instance { class: Rc::clone(&class), fields: HashMap::new() };
+                self.stack[callee_slot] = Value::Instance(Rc::new(RefCell::new(instance)));
+
+                let initializer = class.borrow().methods.get(&self.init_string).cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => Err(self.runtime_error(
+                        format!("Expected 0 arguments but got {}.", arg_count)
+                    )),
+                    None => Ok(()),
+                }
+            },
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::Native(native) => {
+                if !native.arity.accepts(arg_count) {
+                    return Err(self.runtime_error(
+                        format!("Expected {} arguments but got {}.", native.arity, arg_count)
+                    ))
+                }
+
+                let result = (native.body)(&self.stack[callee_slot + 1..])
+                    .map_err(|message| self.runtime_error(message))?;
+                self.stack.truncate(callee_slot);
+                self.push(result);
+                Ok(())
+            },
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<()> {
+        if arg_count != closure.function.arity {
+            return Err(self.runtime_error(
+                format!("Expected {} arguments but got {}.", closure.function.arity, arg_count)
+            ))
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."))
+        }
+
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame { closure, ip: 0, slots });
+        Ok(())
+    }
+
+    fn invoke(&mut self, name: &Rc<str>, arg_count: usize) -> Result<()> {
+        let instance = match self.peek(arg_count) {
+            Value::Instance(instance) => Rc::clone(instance),
+            _ => return Err(self.runtime_error("Only instances have methods.")),
+        };
+
+        // A field holding a function shadows any method of the same name.
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            let callee_slot = self.stack.len() - arg_count - 1;
+            self.stack[callee_slot] = value.clone();
+            return self.call_value(value, arg_count)
+        }
+
+        let class = Rc::clone(&instance.borrow().class);
+        self.invoke_from_class(&class, name, arg_count)
+    }
+
+    fn invoke_from_class(&mut self, class: &Rc<RefCell<Class>>, name: &Rc<str>, arg_count: usize) -> Result<()> {
+        let method = class.borrow().methods.get(name).cloned();
+        match method {
+            Some(method) => self.call(method, arg_count),
+            None => Err(self.runtime_error(format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name`,
+    /// bound to that instance.
+    fn bind_method(&mut self, class: &Rc<RefCell<Class>>, name: &Rc<str>) -> Result<()> {
+        let method = class.borrow().methods.get(name).cloned();
+        let method = match method {
+            Some(method) => method,
+            None => return Err(self.runtime_error(format!("Undefined property '{}'.", name))),
+        };
+
+        let receiver = self.pop();
+        self.push(Value::BoundMethod(Rc::new(BoundMethod { receiver, method })));
+        Ok(())
+    }
+
+    // Upvalues
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self.open_upvalues.iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s >= slot));
+
+        if let Some(i) = position {
+            if let Upvalue::Open(s) = *self.open_upvalues[i].borrow() {
+                if s == slot {
+                    return Rc::clone(&self.open_upvalues[i])
+                }
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let index = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(index, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves every variable at or above `last` off the stack and into the
+    /// upvalues which captured it.
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= last => slot,
+                _ => break,
+            };
+            let upvalue = self.open_upvalues.pop().expect("We just looked at this upvalue");
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+        }
+    }
+
+    // Errors
+
+    /// Builds an error with a trace of the current call stack, and resets
+    /// the stack so that the VM can carry on running new code.
+    fn runtime_error<S: Into<String>>(&mut self, message: S) -> RuntimeError {
+        let trace = self.frames.iter()
+            .rev()
+            .map(|frame| {
+                let function = &frame.closure.function;
+                TraceFrame {
+                    line: function.chunk.line(frame.ip.saturating_sub(1)),
+                    function: function.name.clone(),
+                }
+            })
+            .collect();
+
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+
+        RuntimeError { message: message.into(), trace }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> String {
+        let mut out = Vec::new();
+        Vm::new(&mut out).interpret(source).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn runtime_error(source: &str) -> String {
+        match Vm::new(Vec::new()).interpret(source) {
+            Err(InterpretError::Runtime(e)) => e.to_string(),
+            _ => panic!("Expected a runtime error."),
+        }
+    }
+
+    #[test]
+    fn evaluates_arithmetic() {
+        assert_eq!("7\n-1\ntrue\n", run("print 1 + 2 * 3; print -(4 - 3); print !(1 > 2);"));
+    }
+
+    #[test]
+    fn concatenates_strings() {
+        assert_eq!("hello world\n", run("var a = \"hello\"; print a + \" world\";"));
+    }
+
+    #[test]
+    fn scopes_locals() {
+        assert_eq!("inner\nouter\n", run("var a = \"outer\"; { var a = \"inner\"; print a; } print a;"));
+    }
+
+    #[test]
+    fn runs_loops() {
+        assert_eq!(
+            "0\n1\n2\n3\n",
+            run("for (var i = 0; i < 2; i = i + 1) print i; var j = 2; while (j < 4) { print j; j = j + 1; }")
+        );
+    }
+
+    #[test]
+    fn closures_capture_variables() {
+        let source = "
+            fun counter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            var c = counter();
+            c();
+            print c();
+        ";
+        assert_eq!("2\n", run(source));
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = "
+            var get; var set;
+            {
+                var a = 1;
+                fun g() { return a; }
+                fun s(v) { a = v; }
+                get = g; set = s;
+            }
+            set(5);
+            print get();
+        ";
+        assert_eq!("5\n", run(source));
+    }
+
+    #[test]
+    fn classes_have_methods_and_initializers() {
+        let source = "
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                sum() { return this.x + this.y; }
+            }
+            var p = Point(1, 2);
+            print p.sum();
+            var m = p.sum;
+            p.x = 10;
+            print m();
+            print p;
+        ";
+        assert_eq!("3\n12\nPoint instance\n", run(source));
+    }
+
+    #[test]
+    fn subclasses_can_call_super() {
+        let source = "
+            class A { name() { return \"A\"; } }
+            class B < A { name() { return \"B then \" + super.name(); } }
+            print B().name();
+        ";
+        assert_eq!("B then A\n", run(source));
+    }
+
+    #[test]
+    fn runtime_errors_include_a_trace() {
+        let source = "fun f() {\n  return -\"a\";\n}\nf();";
+        assert_eq!("Operand must be a number.\n[line 2] in f()\n[line 4] in script", runtime_error(source));
+    }
+
+    #[test]
+    fn deep_recursion_overflows_the_stack() {
+        assert!(runtime_error("fun f() { f(); } f();").starts_with("Stack overflow."));
+    }
+
+    #[test]
+    fn globals_persist_between_runs() {
+        let mut vm = Vm::new(Vec::new());
+        vm.interpret("var a = 1;").unwrap();
+        vm.interpret("a = a + 2;").unwrap();
+        assert_eq!(Some(Value::Number(3.0)), vm.get_global("a"));
+    }
+
+    #[test]
+    fn the_vm_recovers_after_runtime_errors() {
+        let mut vm = Vm::new(Vec::new());
+        assert!(vm.interpret("var a = 1; a();").is_err());
+        vm.interpret("var b = a + 1;").unwrap();
+        assert_eq!(Some(Value::Number(2.0)), vm.get_global("b"));
+    }
+
+    #[test]
+    fn natives_are_called_with_their_arguments() {
+        let mut vm = Vm::new(Vec::new());
+        vm.define_native("add", Arity::Exactly(2), |args| match args {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a + b)),
+            _ => Err("add only accepts numbers.".to_string()),
+        });
+        vm.interpret("var sum = add(1, 2);").unwrap();
+        assert_eq!(Some(Value::Number(3.0)), vm.get_global("sum"));
+        assert!(vm.interpret("add(1, \"2\");").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,150 @@
+use rlox_treewalk::Lox;
+use rlox_vm::{error::InterpretError, Vm};
+
+/// Runs `source` on both backends, checking that they print the same thing
+/// and agree on whether it succeeded.
+fn assert_backends_agree(source: &str) {
+    let mut treewalk_out = Vec::new();
+    let treewalk_result = Lox::new(&mut treewalk_out).run_source(source);
+
+    let mut vm_out = Vec::new();
+    let vm_result = Vm::new(&mut vm_out).interpret(source);
+
+    assert_eq!(
+        String::from_utf8(treewalk_out).unwrap(),
+        String::from_utf8(vm_out).unwrap(),
+        "Backends printed different output for:\n{}", source
+    );
+    assert_eq!(treewalk_result.is_ok(), vm_result.is_ok(), "Backends disagree on success for:\n{}", source);
+}
+
+#[test]
+fn arithmetic_and_logic() {
+    assert_backends_agree("
+        print 1 + 2 * 3 - 4 / 8;
+        print -(1.5) + 10;
+        print 1 < 2 and 2 <= 2 and !(3 > 4) and 4 >= 4;
+        print nil or \"default\";
+        print false and undefined;
+        print 1 == 1.0; print \"a\" != \"b\"; print nil == false;
+    ");
+}
+
+#[test]
+fn strings() {
+    assert_backends_agree("
+        var greeting = \"Hello\";
+        print greeting + \", \" + \"world\";
+    ");
+}
+
+#[test]
+fn scopes_and_control_flow() {
+    assert_backends_agree("
+        var a = 1;
+        {
+            var a = 2;
+            { var b = a + 1; print b; }
+            print a;
+        }
+        print a;
+        if (a > 0) print \"positive\"; else print \"negative\";
+        var i = 0;
+        while (i < 3) { print i; i = i + 1; }
+        for (var j = 10; j > 7; j = j - 1) print j;
+    ");
+}
+
+#[test]
+fn functions_and_closures() {
+    assert_backends_agree("
+        fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        print fib(15);
+
+        fun make_counter() {
+            var count = 0;
+            fun counter() { count = count + 1; return count; }
+            return counter;
+        }
+        var first = make_counter();
+        var second = make_counter();
+        first(); first();
+        print first();
+        print second();
+
+        var closures = nil;
+        for (var i = 0; i < 3; i = i + 1) {
+            fun show() { print i; }
+            if (i == 1) closures = show;
+        }
+        closures();
+        print fib;
+    ");
+}
+
+#[test]
+fn classes_and_inheritance() {
+    assert_backends_agree("
+        class Shape {
+            init(name) { this.name = name; }
+            describe() { return this.name + \" with area \" + this.area(); }
+            area() { return 0; }
+        }
+        class Square < Shape {
+            init(side) { super.init(\"square\"); this.side = side; }
+            area() { return this.side * this.side; }
+        }
+        var s = Square(3);
+        print s.describe();
+        var describe = s.describe;
+        s.name = \"renamed\";
+        print describe();
+        print s;
+        print s.init(4).side;
+    ");
+}
+
+#[test]
+fn runtime_errors() {
+    assert_backends_agree("print 1; print -\"a\"; print 2;");
+    assert_backends_agree("print undefined;");
+    assert_backends_agree("var a = \"a\"; a();");
+    assert_backends_agree("fun f(a) {} f(1, 2);");
+    assert_backends_agree("class A {} A().missing;");
+}
+
+#[test]
+fn compile_errors() {
+    assert_backends_agree("print 1 +;");
+    assert_backends_agree("return 1;");
+    assert_backends_agree("{ var a = a; }");
+    assert_backends_agree("class A < A {}");
+}
+
+/// The VM stops where the book does, so nothing the tree-walker has gained
+/// since can be compared. Instead, the VM has to refuse it outright rather
+/// than run it differently.
+#[test]
+fn later_features_are_rejected_by_the_vm() {
+    let syntax = [
+        "print 5 % 3;",
+        "while (true) break;",
+        "for (var i = 0; i < 1; i = i + 1) continue;",
+        "print \"a${1}b\";",
+        "var l = [1, 2]; l[0] = 3;",
+        "print {\"a\": 1};",
+        "var f = fun () {};",
+        "class A { get { return 1; } set = (v) {} class make() {} }",
+        "try { throw 1; } catch (e) {} finally {}",
+    ];
+    for source in syntax {
+        assert!(Lox::new(Vec::new()).run_source(source).is_ok(), "{}", source);
+        assert!(matches!(Vm::new(Vec::new()).interpret(source), Err(InterpretError::Compile(_))), "{}", source);
+    }
+
+    let globals_and_methods = ["print sqrt(4);", "print PI;", "print Error;", "print \"a\".len();"];
+    for source in globals_and_methods {
+        assert!(Lox::new(Vec::new()).run_source(source).is_ok(), "{}", source);
+        assert!(matches!(Vm::new(Vec::new()).interpret(source), Err(InterpretError::Runtime(_))), "{}", source);
+    }
+}
\ No newline at end of file